@@ -0,0 +1,170 @@
+use std::f64::consts::PI;
+
+use elgan_math::linalg::*;
+
+use crate::camera::Camera;
+
+const MAX_BOUNCES: u32 = 64;
+
+/// Simple motions that give an object's position at a time in seconds,
+/// meant to be used from the scene callback of `render_animation_with`.
+#[derive(Clone, Copy)]
+pub enum Motion {
+    /// Moves in a straight line from `start`.
+    ConstantVelocity {
+        start: ColumnVec<3>,
+        velocity: ColumnVec<3>,
+    },
+    /// Falls under `gravity` (pulling towards -y) and bounces off the horizontal plane at
+    /// height `floor`, keeping `restitution` of its vertical speed each bounce.
+    /// For a sphere, `floor` is the ground height plus its radius. Without positive gravity
+    /// it moves at constant velocity, and a start below the floor bounces straight away.
+    Bounce {
+        start: ColumnVec<3>,
+        velocity: ColumnVec<3>,
+        gravity: f64,
+        floor: f64,
+        restitution: f64,
+    },
+    /// Circles `center` in the horizontal plane, taking `period` seconds per revolution.
+    Orbit {
+        center: ColumnVec<3>,
+        radius: f64,
+        period: f64,
+        phase: f64,
+    },
+}
+
+impl Motion {
+    pub fn position(&self, t: f64) -> ColumnVec<3> {
+        match *self {
+            Motion::ConstantVelocity { start, velocity } => start + t * velocity,
+            Motion::Bounce {
+                start,
+                velocity,
+                gravity,
+                floor,
+                restitution,
+            } => {
+                if gravity <= 0. {
+                    return start + t * velocity;
+                }
+                let fall = |t: f64| ColumnVec([0., gravity * t * t / 2., 0.]);
+                let mut position = start;
+                let mut velocity = velocity;
+                let mut t = t;
+                for _ in 0..MAX_BOUNCES {
+                    let height = (position[1] - floor).max(0.);
+                    let t_hit = (velocity[1]
+                        + (velocity[1] * velocity[1] + 2. * gravity * height).sqrt())
+                        / gravity;
+                    if t < t_hit {
+                        return position + t * velocity - fall(t);
+                    }
+                    position = position + t_hit * velocity - fall(t_hit);
+                    velocity = ColumnVec([
+                        velocity[0],
+                        -(velocity[1] - gravity * t_hit) * restitution,
+                        velocity[2],
+                    ]);
+                    t -= t_hit;
+                }
+                ColumnVec([
+                    position[0] + t * velocity[0],
+                    floor,
+                    position[2] + t * velocity[2],
+                ])
+            }
+            Motion::Orbit {
+                center,
+                radius,
+                period,
+                phase,
+            } => {
+                let angle = 2. * PI * t / period + phase;
+                center + radius * ColumnVec([angle.cos(), 0., angle.sin()])
+            }
+        }
+    }
+}
+
+/// Depth of field settings at a time in seconds.
+#[derive(Clone, Copy)]
+pub struct FocusKey {
+    pub time: f64,
+    pub aperture: f64,
+    pub focus_distance: f64,
+}
+
+/// Animates a camera's aperture and focus between keys, for rack focus shots.
+/// Focus is interpolated in diopters (the inverse of the distance) like turning a focus ring,
+/// so it moves quickly through near distances and slowly towards infinity, and each move
+/// eases in and out.
+#[derive(Clone)]
+pub struct FocusTrack {
+    keys: Vec<FocusKey>,
+    /// How much the field of view widens per unit of `focal_length / focus_distance`,
+    /// imitating the focus breathing of real lenses. Zero keeps the view fixed.
+    pub breathing: f64,
+}
+
+impl FocusTrack {
+    pub fn new(mut keys: Vec<FocusKey>) -> Self {
+        keys.sort_by(|a, b| a.time.total_cmp(&b.time));
+        Self {
+            keys,
+            breathing: 0.,
+        }
+    }
+
+    /// The aperture and focus distance at time `t`, held at the first and last keys outside
+    /// their range. Returns `None` if there are no keys.
+    pub fn at(&self, t: f64) -> Option<(f64, f64)> {
+        let first = self.keys.first()?;
+        let next = match self.keys.iter().position(|key| key.time > t) {
+            Some(0) => return Some((first.aperture, first.focus_distance)),
+            Some(next) => next,
+            None => {
+                let last = self.keys.last()?;
+                return Some((last.aperture, last.focus_distance));
+            }
+        };
+        let (a, b) = (self.keys[next - 1], self.keys[next]);
+        let s = (t - a.time) / (b.time - a.time);
+        let s = s * s * (3. - 2. * s);
+        let aperture = a.aperture + (b.aperture - a.aperture) * s;
+        let diopters = (1. - s) / a.focus_distance + s / b.focus_distance;
+        Some((aperture, 1. / diopters))
+    }
+
+    /// `camera` with the depth of field, and view if breathing, of time `t`.
+    pub fn apply(&self, camera: Camera, t: f64) -> Camera {
+        match self.at(t) {
+            Some((aperture, focus_distance)) => camera
+                .with_depth_of_field(aperture, focus_distance)
+                .with_view_scale(1. + self.breathing * camera.focal_length / focus_distance),
+            None => camera,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bounce_without_gravity_moves_in_a_straight_line() {
+        let motion = Motion::Bounce {
+            start: ColumnVec([0., 2., 0.]),
+            velocity: ColumnVec([1., -1., 0.]),
+            gravity: 0.,
+            floor: 0.,
+            restitution: 0.5,
+        };
+        for t in [0., 0.5, 1., 3.] {
+            let position = motion.position(t);
+            assert!(position.0.iter().all(|x| x.is_finite()));
+            assert!(position.close_enough(ColumnVec([t, 2. - t, 0.])));
+        }
+    }
+}
@@ -0,0 +1,58 @@
+use elgan_math::linalg::*;
+
+use crate::{Ray, RayKind};
+
+/// What a ray sees when it escapes the scene.
+#[derive(Clone, Copy)]
+pub enum Background {
+    Solid(ColumnVec<3>),
+    /// Blends from the first color looking straight down to the second looking straight up.
+    Gradient(ColumnVec<3>, ColumnVec<3>),
+}
+
+impl Background {
+    pub fn color(&self, direction: ColumnVec<3>) -> ColumnVec<3> {
+        match self {
+            Background::Solid(x) => *x,
+            Background::Gradient(bottom, top) => {
+                let t = 0.5 * direction.normalized()[1] + 0.5;
+                (1. - t) * *bottom + t * *top
+            }
+        }
+    }
+}
+
+impl Default for Background {
+    fn default() -> Self {
+        // Background::Gradient(ColumnVec([1.; 3]), ColumnVec([0.5, 0.7, 1.0]))
+        Background::Gradient(ColumnVec([0.08, 0.1, 0.2]), ColumnVec([0.032, 0.04, 0.08]))
+    }
+}
+
+/// Separate backgrounds for camera rays, mirror-like reflections and refractions, and diffuse
+/// bounces, so e.g. a plain backdrop can be shown while a brighter environment lights the scene.
+#[derive(Clone, Copy, Default)]
+pub struct Backgrounds {
+    pub camera: Background,
+    pub specular: Background,
+    pub diffuse: Background,
+}
+
+impl Backgrounds {
+    /// The same background for every kind of ray.
+    pub fn all(background: Background) -> Self {
+        Self {
+            camera: background,
+            specular: background,
+            diffuse: background,
+        }
+    }
+
+    pub fn color(&self, ray: Ray) -> ColumnVec<3> {
+        match ray.kind {
+            RayKind::Camera => self.camera.color(ray.direction),
+            RayKind::Specular | RayKind::Transmission => self.specular.color(ray.direction),
+            RayKind::Diffuse => self.diffuse.color(ray.direction),
+        }
+    }
+}
@@ -0,0 +1,160 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use elgan_math::linalg::ColumnVec;
+
+use crate::{
+    camera::Camera,
+    hittable::{SphereList, World},
+    material::{ColorType, Dielectric, Lambertian, Material, Metal},
+    regression::{image_difference, presets},
+    render::{render_image, RenderSettings},
+};
+
+/// Settings for `bench_render`.
+#[derive(Clone)]
+pub struct BenchSettings {
+    /// Samples per pixel used to measure rays per second.
+    pub samples_per_pixel: u32,
+    /// RMS noise, from 0 to 1, a scene must reach for `time_to_noise` to be reported.
+    pub target_noise: f64,
+    /// Stop looking for the target noise past this many samples per pixel.
+    pub max_samples_per_pixel: u32,
+}
+
+impl Default for BenchSettings {
+    fn default() -> Self {
+        Self {
+            samples_per_pixel: 16,
+            target_noise: 0.02,
+            max_samples_per_pixel: 1024,
+        }
+    }
+}
+
+pub struct BenchResult {
+    pub scene: &'static str,
+    /// Camera rays traced per second. Secondary rays are not counted.
+    pub rays_per_second: f64,
+    /// Time taken by the first render with noise below the target, if one was reached.
+    pub time_to_noise: Option<Duration>,
+}
+
+/// A grid of `count` x `count` small spheres in alternating materials over a ground plane,
+/// laid out the same way every time so timings are comparable between runs.
+pub fn sphere_grid(count: u32) -> (World, Camera) {
+    let materials: [Arc<dyn Material>; 3] = [
+        Arc::new(Lambertian {
+            color: ColorType::Solid(ColumnVec([0.7, 0.4, 0.3])),
+        }),
+        Arc::new(Metal {
+            color: ColorType::Solid(ColumnVec([0.8; 3])),
+            fuzz: 0.2,
+        }),
+        Arc::new(Dielectric {
+            color: ColorType::Solid(ColumnVec([1.; 3])),
+            ir: 1.5,
+            priority: 0,
+        }),
+    ];
+    let mut spheres = SphereList::new();
+    spheres.push(
+        ColumnVec([0., -1000.5, -1.]),
+        1000.,
+        Arc::new(Lambertian {
+            color: ColorType::Checker(ColumnVec([0.4, 0.8, 0.4]), ColumnVec([0.6, 1., 0.6]), 0.5),
+        }),
+    );
+    let spacing = 8. / count as f64;
+    for i in 0..count {
+        for j in 0..count {
+            spheres.push(
+                ColumnVec([
+                    (i as f64 + 0.5) * spacing - 4.,
+                    spacing * 0.4 - 0.5,
+                    -(j as f64 + 0.5) * spacing - 1.,
+                ]),
+                spacing * 0.4,
+                materials[((i + j) % 3) as usize].clone(),
+            );
+        }
+    }
+    let mut world = World::new();
+    world.push(Box::new(spheres));
+    (world, Camera::new(160, 90, 2., 1., ColumnVec([0., 1., 0.])))
+}
+
+/// The scenes measured by `bench_render`: the regression presets and two sphere grids.
+pub fn scenes() -> Vec<(&'static str, World, Camera)> {
+    let mut scenes = presets();
+    let (world, camera) = sphere_grid(10);
+    scenes.push(("grid_100", world, camera));
+    let (world, camera) = sphere_grid(40);
+    scenes.push(("grid_1600", world, camera));
+    scenes
+}
+
+/// Measures every scene in `scenes` and prints a line for each.
+pub fn bench_render(settings: &BenchSettings) -> Vec<BenchResult> {
+    scenes()
+        .into_iter()
+        .map(|(scene, world, camera)| {
+            let render = |samples_per_pixel, frame| {
+                render_image(
+                    &world,
+                    camera,
+                    &RenderSettings {
+                        frame,
+                        ..RenderSettings::new(samples_per_pixel)
+                    },
+                    0..camera.width,
+                    0..camera.height,
+                )
+            };
+
+            let started = Instant::now();
+            render(settings.samples_per_pixel, 0);
+            let rays =
+                camera.width as f64 * camera.height as f64 * settings.samples_per_pixel as f64;
+            let rays_per_second = rays / started.elapsed().as_secs_f64();
+
+            // Two independent renders differ by about sqrt(2) times the noise in each. The
+            // second is rendered as another frame so its pixels are sampled at other positions.
+            let mut time_to_noise = None;
+            let mut samples_per_pixel = 1;
+            while samples_per_pixel <= settings.max_samples_per_pixel {
+                let started = Instant::now();
+                let a = render(samples_per_pixel, 0);
+                let time = started.elapsed();
+                let b = render(samples_per_pixel, 1);
+                let noise = image_difference(&a, &b).unwrap() / 2f64.sqrt();
+                if noise <= settings.target_noise {
+                    time_to_noise = Some(time);
+                    break;
+                }
+                samples_per_pixel *= 2;
+            }
+
+            match time_to_noise {
+                Some(time) => println!(
+                    "{}: {:.0} rays/s, {:.2} s to noise {}",
+                    scene,
+                    rays_per_second,
+                    time.as_secs_f64(),
+                    settings.target_noise
+                ),
+                None => println!(
+                    "{}: {:.0} rays/s, noise {} not reached",
+                    scene, rays_per_second, settings.target_noise
+                ),
+            }
+            BenchResult {
+                scene,
+                rays_per_second,
+                time_to_noise,
+            }
+        })
+        .collect()
+}
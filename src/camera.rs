@@ -1,6 +1,9 @@
 use elgan_math::linalg::*;
 
-use crate::Ray;
+use crate::{
+    hittable::{BoundingSphere, Hittable, World},
+    sampler, Ray,
+};
 
 #[derive(Clone, Copy)]
 pub struct Camera {
@@ -14,6 +17,12 @@ pub struct Camera {
     pub horizontal: ColumnVec<3>,
     pub vertical: ColumnVec<3>,
     pub lower_left: ColumnVec<3>,
+    pub aperture: f64,
+    pub focus_distance: f64,
+    pub near: f64,
+    pub far: f64,
+    /// How much the focus distance of red and blue light differs from green, as a fraction.
+    pub chromatic_aberration: f64,
 }
 
 impl Camera {
@@ -41,13 +50,168 @@ impl Camera {
             horizontal,
             vertical,
             lower_left,
+            aperture: 0.,
+            focus_distance: focal_length,
+            near: 0.,
+            far: f64::INFINITY,
+            chromatic_aberration: 0.,
+        }
+    }
+
+    /// Only shows geometry between the planes `near` and `far` in front of the camera,
+    /// for cutaway views. Reflections and other secondary rays are not clipped.
+    pub fn with_clipping(self, near: f64, far: f64) -> Self {
+        Self { near, far, ..self }
+    }
+
+    /// The range of `t` along a ray from this camera that lies between the clipping planes.
+    pub fn clip_range(self, ray: Ray) -> (f64, f64) {
+        let forward = (self.lower_left + self.horizontal / 2. + self.vertical / 2. - self.origin)
+            .normalized();
+        let depth = ray.direction * forward;
+        (self.near / depth, self.far / depth)
+    }
+
+    /// Turns the camera into a thin lens of the given diameter, focused on the plane
+    /// `focus_distance` in front of it. An aperture of 0 is a pinhole camera.
+    pub fn with_depth_of_field(self, aperture: f64, focus_distance: f64) -> Self {
+        Self {
+            aperture,
+            focus_distance,
+            ..self
+        }
+    }
+
+    /// Focuses red light `shift` times the focus distance further away than green, and blue
+    /// the same amount closer, giving colored fringes on out of focus edges like a real lens.
+    /// Only has an effect with depth of field.
+    pub fn with_chromatic_aberration(self, shift: f64) -> Self {
+        Self {
+            chromatic_aberration: shift,
+            ..self
+        }
+    }
+
+    /// Scales the field of view around its center by `scale`, keeping the direction the
+    /// camera looks in.
+    pub fn with_view_scale(self, scale: f64) -> Self {
+        let center = self.lower_left + self.horizontal / 2. + self.vertical / 2.;
+        let (horizontal, vertical) = (self.horizontal * scale, self.vertical * scale);
+        Self {
+            view_height: self.view_height * scale,
+            view_width: self.view_width * scale,
+            horizontal,
+            vertical,
+            lower_left: center - horizontal / 2. - vertical / 2.,
+            ..self
         }
     }
 
     pub fn get_ray(self, u: f64, v: f64) -> Ray {
-        Ray {
-            origin: self.origin,
-            direction: self.lower_left + u * self.horizontal + v * self.vertical - self.origin,
+        self.lens_ray(u, v, self.focus_distance)
+    }
+
+    /// A ray for the given screen position and the weight of the light it carries in each
+    /// color channel. With chromatic aberration each ray is focused for one random channel,
+    /// weighted so the channels average out to the right brightness.
+    pub fn sample_ray(self, u: f64, v: f64) -> (Ray, ColumnVec<3>) {
+        if self.chromatic_aberration == 0. || self.aperture <= 0. {
+            return (self.get_ray(u, v), ColumnVec([1.; 3]));
         }
+        let channel = ((sampler::random() * 3.) as usize).min(2);
+        let mut weight = ColumnVec::zero();
+        weight.0[channel] = 3.;
+        let shift = 1. + self.chromatic_aberration * (1. - channel as f64);
+        (self.lens_ray(u, v, self.focus_distance * shift), weight)
+    }
+
+    fn lens_ray(self, u: f64, v: f64, focus_distance: f64) -> Ray {
+        let ray = self.pinhole_ray(u, v);
+        if self.aperture <= 0. {
+            return ray;
+        }
+        let target = ray.at(focus_distance / self.focal_length);
+        let radius = self.aperture / 2. * sampler::random().sqrt();
+        let angle = 2. * std::f64::consts::PI * sampler::random();
+        let origin = self.origin
+            + radius * angle.cos() * self.horizontal.normalized()
+            + radius * angle.sin() * self.vertical.normalized();
+        Ray::new(origin, target - origin)
+    }
+
+    /// The ray through the center of the lens, ignoring depth of field.
+    pub fn pinhole_ray(self, u: f64, v: f64) -> Ray {
+        Ray::new(
+            self.origin,
+            self.lower_left + u * self.horizontal + v * self.vertical - self.origin,
+        )
+    }
+
+    /// Moves and turns the camera to look along `direction` at the whole of `world`, with a
+    /// vertical field of view of `fov` degrees, focused on the middle of the scene.
+    /// Returns none if the world has unbounded objects.
+    pub fn frame_world(self, world: &World, fov: f64, direction: ColumnVec<3>) -> Option<Self> {
+        let bounds = world.bounds()?;
+        let forward = direction.normalized();
+        let up = if forward[1].abs() < 0.999 {
+            ColumnVec([0., 1., 0.])
+        } else {
+            ColumnVec([0., 0., -1.])
+        };
+        let right = forward.cross(up).normalized();
+        let up = right.cross(forward);
+
+        let half_height = (fov.to_radians() / 2.).tan();
+        let half_width = half_height * self.aspect;
+        // The sphere fits if it fits the narrower of the two half angles.
+        let half_angle = half_height.min(half_width).atan();
+        let distance = bounds.radius / half_angle.sin();
+        let origin = bounds.center - forward * distance;
+
+        let view_height = 2. * self.focal_length * half_height;
+        let view_width = view_height * self.aspect;
+        let horizontal = right * view_width;
+        let vertical = up * view_height;
+        Some(Self {
+            view_height,
+            view_width,
+            origin,
+            horizontal,
+            vertical,
+            lower_left: origin + forward * self.focal_length - horizontal / 2. - vertical / 2.,
+            focus_distance: distance,
+            ..self
+        })
+    }
+
+    /// Focuses on whatever is visible at the given pixel, for click to focus.
+    /// Returns the new focus distance, or none if nothing was hit or the pixel is outside
+    /// the image, in which case the focus distance is left unchanged.
+    pub fn focus_on(&mut self, world: &World, screen_x: u32, screen_y: u32) -> Option<f64> {
+        let pick = world.pick(screen_x, screen_y, self)?;
+        self.focus_distance = pick.t * self.focal_length;
+        Some(self.focus_distance)
+    }
+
+    /// Whether any part of the sphere lies inside the view frustum.
+    pub fn sees(self, bounds: BoundingSphere) -> bool {
+        let corners = [
+            self.lower_left,
+            self.lower_left + self.horizontal,
+            self.lower_left + self.horizontal + self.vertical,
+            self.lower_left + self.vertical,
+        ]
+        .map(|corner| corner - self.origin);
+        let forward = self.lower_left + self.horizontal / 2. + self.vertical / 2. - self.origin;
+        let offset = bounds.center - self.origin;
+        (0..4).all(|k| {
+            let normal = corners[k].cross(corners[(k + 1) % 4]).normalized();
+            let normal = if normal * forward < 0. {
+                -normal
+            } else {
+                normal
+            };
+            offset * normal >= -bounds.radius
+        })
     }
 }
@@ -0,0 +1,153 @@
+use elgan_math::linalg::ColumnVec;
+use image::Rgb;
+
+use crate::{dither_threshold, to_color_dithered};
+
+/// The color space images are written in. Colors are computed with Rec.709 (sRGB) primaries
+/// and converted when the image is written.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum ColorSpace {
+    #[default]
+    Rec709,
+    DisplayP3,
+    /// Linear, as used for compositing in ACES pipelines.
+    AcesCg,
+}
+
+impl ColorSpace {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Rec709 => "Rec.709",
+            Self::DisplayP3 => "Display P3",
+            Self::AcesCg => "ACEScg",
+        }
+    }
+
+    /// Rows of the matrix taking linear Rec.709 colors to this space's primaries.
+    fn rec709_matrix(self) -> [ColumnVec<3>; 3] {
+        match self {
+            Self::Rec709 => [
+                ColumnVec([1., 0., 0.]),
+                ColumnVec([0., 1., 0.]),
+                ColumnVec([0., 0., 1.]),
+            ],
+            Self::DisplayP3 => [
+                ColumnVec([0.822462, 0.177538, 0.]),
+                ColumnVec([0.033194, 0.966806, 0.]),
+                ColumnVec([0.017083, 0.072397, 0.910520]),
+            ],
+            // Includes the chromatic adaptation from D65 to the ACES white point.
+            Self::AcesCg => [
+                ColumnVec([0.613097, 0.339523, 0.047379]),
+                ColumnVec([0.070194, 0.916354, 0.013452]),
+                ColumnVec([0.020616, 0.109570, 0.869815]),
+            ],
+        }
+    }
+
+    /// Converts a linear Rec.709 color to linear values in this space.
+    pub fn convert_rec709(self, color: ColumnVec<3>) -> ColumnVec<3> {
+        ColumnVec(self.rec709_matrix().map(|row| row * color))
+    }
+
+    /// Converts and quantizes a linear Rec.709 color for the pixel at `x`, `y`. ACEScg is
+    /// written linear; the display spaces use the same gamma as `to_color`.
+    pub fn to_color_dithered(self, color: ColumnVec<3>, x: u32, y: u32) -> Rgb<u8> {
+        let color = self.convert_rec709(color);
+        match self {
+            Self::AcesCg => {
+                let threshold = dither_threshold(x, y);
+                Rgb(color
+                    .0
+                    .map(|i: f64| (i.clamp(0., 1.) * 255. + threshold).min(255.) as u8))
+            }
+            _ => to_color_dithered(color, x, y),
+        }
+    }
+}
+
+/// A 3D lookup table mapping display colors to graded colors, as read from a .cube file.
+#[derive(Clone)]
+pub struct Lut3d {
+    pub size: usize,
+    pub domain_min: ColumnVec<3>,
+    pub domain_max: ColumnVec<3>,
+    /// Output colors with red changing fastest, then green, then blue.
+    pub table: Vec<ColumnVec<3>>,
+}
+
+impl Lut3d {
+    /// Reads a 3D LUT in the .cube format. Returns `None` if the file can't be read or isn't a
+    /// valid 3D LUT.
+    pub fn load(filename: &str) -> Option<Self> {
+        let text = std::fs::read_to_string(filename).ok()?;
+        let mut size = 0;
+        let mut domain_min = ColumnVec([0.; 3]);
+        let mut domain_max = ColumnVec([1.; 3]);
+        let mut table = Vec::new();
+        let triple = |words: &[&str]| -> Option<ColumnVec<3>> {
+            match words {
+                [r, g, b] => Some(ColumnVec([
+                    r.parse().ok()?,
+                    g.parse().ok()?,
+                    b.parse().ok()?,
+                ])),
+                _ => None,
+            }
+        };
+        for line in text.lines() {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words[..] {
+                [] => {}
+                [word, ..] if word.starts_with('#') || word == "TITLE" => {}
+                ["LUT_3D_SIZE", n] => size = n.parse().ok()?,
+                ["DOMAIN_MIN", ..] => domain_min = triple(&words[1..])?,
+                ["DOMAIN_MAX", ..] => domain_max = triple(&words[1..])?,
+                _ => table.push(triple(&words)?),
+            }
+        }
+        if size < 2 || table.len() != size * size * size {
+            return None;
+        }
+        Some(Self {
+            size,
+            domain_min,
+            domain_max,
+            table,
+        })
+    }
+
+    fn entry(&self, r: usize, g: usize, b: usize) -> ColumnVec<3> {
+        self.table[r + self.size * (g + self.size * b)]
+    }
+
+    /// Looks up `color`, interpolating trilinearly between table entries.
+    pub fn apply(&self, color: ColumnVec<3>) -> ColumnVec<3> {
+        let last = (self.size - 1) as f64;
+        let mut index = [0; 3];
+        let mut fraction = [0.; 3];
+        for axis in 0..3 {
+            let t = (color[axis] - self.domain_min[axis])
+                / (self.domain_max[axis] - self.domain_min[axis]);
+            let position = t.clamp(0., 1.) * last;
+            index[axis] = (position.floor() as usize).min(self.size - 2);
+            fraction[axis] = position - index[axis] as f64;
+        }
+        let [r, g, b] = index;
+        let [fr, fg, fb] = fraction;
+        let lerp = |a: ColumnVec<3>, b: ColumnVec<3>, t: f64| a * (1. - t) + b * t;
+        let blue = |b| {
+            lerp(
+                lerp(self.entry(r, g, b), self.entry(r + 1, g, b), fr),
+                lerp(self.entry(r, g + 1, b), self.entry(r + 1, g + 1, b), fr),
+                fg,
+            )
+        };
+        lerp(blue(b), blue(b + 1), fb)
+    }
+
+    pub fn apply_to_pixel(&self, pixel: Rgb<u8>) -> Rgb<u8> {
+        let color = self.apply(ColumnVec(pixel.0.map(|c| c as f64 / 255.)));
+        Rgb(color.0.map(|c| (c.clamp(0., 1.) * 255. + 0.5) as u8))
+    }
+}
@@ -0,0 +1,78 @@
+use std::{
+    io::{Cursor, Read, Result, Write},
+    net::{TcpListener, TcpStream},
+    sync::Arc,
+    thread,
+};
+
+use image::{ImageBuffer, ImageOutputFormat, Rgb, RgbImage};
+
+use crate::render::Progress;
+
+/// Serves a page at `address` showing the image, finished columns and remaining time
+/// of the render in progress. Runs on its own thread until the program exits.
+pub fn serve(address: &str, progress: Arc<Progress>) -> Result<()> {
+    let listener = TcpListener::bind(address)?;
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let _ = respond(stream, &progress);
+        }
+    });
+    Ok(())
+}
+
+fn respond(mut stream: TcpStream, progress: &Progress) -> Result<()> {
+    let mut request = [0; 1024];
+    let length = stream.read(&mut request)?;
+    let request = String::from_utf8_lossy(&request[..length]);
+    let (content_type, body) = match request.split_whitespace().nth(1) {
+        Some("/image.png") => {
+            // Copied so render threads aren't kept waiting for the lock while it's encoded.
+            let image = progress.image.lock().unwrap().clone();
+            ("image/png", png(&image))
+        }
+        Some("/columns.png") => ("image/png", png(&column_map(progress))),
+        _ => ("text/html", page(progress).into_bytes()),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        content_type,
+        body.len()
+    )?;
+    stream.write_all(&body)
+}
+
+fn page(progress: &Progress) -> String {
+    let remaining = match progress.remaining() {
+        Some(remaining) => format!("{}s remaining", remaining.as_secs()),
+        None => "estimating time remaining".to_string(),
+    };
+    format!(
+        "<!DOCTYPE html><html><head><meta http-equiv=\"refresh\" content=\"2\"><title>render progress</title></head>\
+         <body style=\"background:#222;color:#ddd;font-family:sans-serif\">\
+         <p>{:.1}% done, {}, {}s elapsed</p>\
+         <img src=\"/columns.png\" style=\"width:100%;height:12px;image-rendering:pixelated\"><br>\
+         <img src=\"/image.png\" style=\"max-width:100%\"></body></html>",
+        progress.fraction_done() * 100.,
+        remaining,
+        progress.started.elapsed().as_secs()
+    )
+}
+
+fn column_map(progress: &Progress) -> RgbImage {
+    let column_times = progress.column_times.lock().unwrap();
+    ImageBuffer::from_fn(column_times.len() as u32, 1, |x, _| {
+        if column_times[x as usize].is_some() {
+            Rgb([80, 200, 80])
+        } else {
+            Rgb([60, 60, 60])
+        }
+    })
+}
+
+fn png(img: &RgbImage) -> Vec<u8> {
+    let mut bytes = Cursor::new(vec![]);
+    let _ = img.write_to(&mut bytes, ImageOutputFormat::Png);
+    bytes.into_inner()
+}
@@ -1,35 +1,125 @@
 use std::sync::Arc;
 
 use elgan_math::linalg::*;
+use rayon::prelude::{IntoParallelIterator, ParallelExtend};
 
-use crate::{material::Material, Ray};
+use crate::{
+    background::Backgrounds,
+    camera::Camera,
+    material::{Holdout, Material},
+    Ray, RayKind,
+};
 
 #[derive(Clone)]
 pub struct HitRecord {
     pub point: ColumnVec<3>,
+    /// Shading normal, which materials like `NoiseBump` may perturb.
     pub normal: ColumnVec<3>,
+    /// Normal of the actual surface, facing the incoming ray like `normal`.
+    pub geometric_normal: ColumnVec<3>,
     pub t: f64,
     pub front_face: bool,
     pub material: Arc<dyn Material>,
+    /// Index of the hit object in the outermost `World`.
+    pub object_id: usize,
+    /// Index of the hit primitive within its object, for objects made of many primitives.
+    pub primitive_id: usize,
+    /// Bound on how far `point` may be from the true surface due to rounding, which grows
+    /// with the size of the coordinates and the distance travelled along the ray.
+    pub error: f64,
 }
 
+/// Relative rounding error assumed for hit points. Larger than machine epsilon because
+/// solving for grazing hits loses precision.
+const HIT_POINT_ERROR: f64 = 1e-7;
+
 impl HitRecord {
     fn new(ray: Ray, normal: ColumnVec<3>, t: f64, material: Arc<dyn Material>) -> Self {
         let front_face = ray.direction * normal < 0.;
+        let point = ray.at(t);
+        let magnitude = point.0.iter().fold(0., |max: f64, x| max.max(x.abs()));
+        let normal = if front_face { normal } else { -normal };
         Self {
-            point: ray.at(t),
-            normal: if front_face { normal } else { -normal },
+            point,
+            normal,
+            geometric_normal: normal,
             t,
             front_face,
             material,
+            object_id: 0,
+            primitive_id: 0,
+            error: HIT_POINT_ERROR * (magnitude + t.abs() * ray.direction.length()),
+        }
+    }
+
+    /// Origin for a ray leaving the surface in `direction`: the hit point pushed along the
+    /// normal past its rounding error, to the side the ray leaves on, so the new ray can't
+    /// hit the surface it starts on however large the scene is.
+    pub fn spawn_point(&self, direction: ColumnVec<3>) -> ColumnVec<3> {
+        if direction * self.geometric_normal > 0. {
+            self.point + self.geometric_normal * self.error
+        } else {
+            self.point - self.geometric_normal * self.error
         }
     }
 }
 
-pub trait Hittable: Sync {
+#[derive(Clone, Copy)]
+pub struct BoundingSphere {
+    pub center: ColumnVec<3>,
+    pub radius: f64,
+}
+
+impl BoundingSphere {
+    pub fn from_points(points: &[ColumnVec<3>]) -> Self {
+        let center = points
+            .iter()
+            .fold(ColumnVec::zero(), |sum, &point| sum + point)
+            / points.len() as f64;
+        let radius = points
+            .iter()
+            .map(|&point| (point - center).length())
+            .fold(0., f64::max);
+        Self { center, radius }
+    }
+
+    /// Smallest sphere containing both spheres.
+    pub fn union(self, other: Self) -> Self {
+        let distance = (other.center - self.center).length();
+        if distance + other.radius <= self.radius {
+            return self;
+        }
+        if distance + self.radius <= other.radius {
+            return other;
+        }
+        let radius = (distance + self.radius + other.radius) / 2.;
+        Self {
+            center: self.center + (radius - self.radius) / distance * (other.center - self.center),
+            radius,
+        }
+    }
+}
+
+pub trait Hittable: Send + Sync {
     /// If the ray hits the object within the specified bounds, returns a record of the hit.
     /// If the ray does not hit returns none.
     fn hit(&self, ray: Ray, t_min: f64, t_max: f64) -> Option<HitRecord>;
+
+    /// A sphere enclosing the whole object, or none if the object is unbounded.
+    fn bounds(&self) -> Option<BoundingSphere> {
+        None
+    }
+
+    /// Approximate bytes used by the object, not counting materials, which are usually
+    /// shared between objects.
+    fn memory_size(&self) -> usize {
+        std::mem::size_of_val(self)
+    }
+
+    /// The render layer the object belongs to; see `World::active_layer`.
+    fn layer(&self) -> u32 {
+        0
+    }
 }
 
 #[derive(Clone)]
@@ -69,116 +159,399 @@ impl Hittable for Sphere {
         }
         None
     }
+
+    fn bounds(&self) -> Option<BoundingSphere> {
+        Some(BoundingSphere {
+            center: self.center,
+            radius: self.radius,
+        })
+    }
 }
 
+/// Built with `new`, which caches the plane; the vertices can't change afterwards.
 #[derive(Clone)]
 pub struct Triangle {
-    pub vertices: [ColumnVec<3>; 3],
+    vertices: [ColumnVec<3>; 3],
     pub material: Arc<dyn Material>,
+    normal: ColumnVec<3>,
 }
 
 impl Triangle {
+    pub fn new(vertices: [ColumnVec<3>; 3], material: Arc<dyn Material>) -> Self {
+        let normal = (vertices[1] - vertices[0])
+            .cross(vertices[2] - vertices[0])
+            .normalized();
+        Self {
+            vertices,
+            material,
+            normal,
+        }
+    }
+
+    pub fn vertices(&self) -> [ColumnVec<3>; 3] {
+        self.vertices
+    }
+
     pub fn normal(&self) -> ColumnVec<3> {
-        (self.vertices[1] - self.vertices[0])
-            .cross(self.vertices[2] - self.vertices[0])
-            .normalized()
+        self.normal
     }
 }
 
 impl Hittable for Triangle {
+    /// Watertight intersection (Woop, Benthin and Wald 2013): edges shared by two triangles
+    /// are tested identically from both sides, so rays cannot slip between them.
     fn hit(&self, ray: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        let new_ray = Ray {
-            origin: ray.origin - self.vertices[0],
-            direction: ray.direction,
-        };
-        let transform = Matrix::from_columns([
-            self.vertices[1] - self.vertices[0],
-            self.vertices[2] - self.vertices[0],
-            new_ray.direction,
-        ]);
-        let transform_inv = transform.inverse()?;
-        let new_ray = Ray {
-            origin: transform_inv * new_ray.origin,
-            direction: transform_inv * new_ray.direction,
+        let dir = ray.direction;
+        let kz = (0..3)
+            .max_by(|&i, &j| dir[i].abs().total_cmp(&dir[j].abs()))
+            .unwrap();
+        let (kx, ky) = if dir[kz] < 0. {
+            ((kz + 2) % 3, (kz + 1) % 3)
+        } else {
+            ((kz + 1) % 3, (kz + 2) % 3)
         };
-        if new_ray.origin[0] > 0.
-            && new_ray.origin[1] > 0.
-            && new_ray.origin[0] + new_ray.origin[1] < 1.
-            && -new_ray.origin[2] > t_min
-            && -new_ray.origin[2] < t_max
-        {
-            Some(HitRecord::new(
-                ray,
-                self.normal(),
-                -new_ray.origin[2],
-                self.material.clone(),
-            ))
+        let shear = [dir[kx] / dir[kz], dir[ky] / dir[kz], 1. / dir[kz]];
+        let [a, b, c] = self.vertices.map(|vertex| {
+            let p = vertex - ray.origin;
+            [
+                p[kx] - shear[0] * p[kz],
+                p[ky] - shear[1] * p[kz],
+                shear[2] * p[kz],
+            ]
+        });
+        let u = c[0] * b[1] - c[1] * b[0];
+        let v = a[0] * c[1] - a[1] * c[0];
+        let w = b[0] * a[1] - b[1] * a[0];
+        if (u < 0. || v < 0. || w < 0.) && (u > 0. || v > 0. || w > 0.) {
+            return None;
+        }
+        let det = u + v + w;
+        if det == 0. {
+            return None;
+        }
+        let t = (u * a[2] + v * b[2] + w * c[2]) / det;
+        if t_min < t && t < t_max {
+            Some(HitRecord::new(ray, self.normal, t, self.material.clone()))
         } else {
             None
         }
     }
+
+    fn bounds(&self) -> Option<BoundingSphere> {
+        Some(BoundingSphere::from_points(&self.vertices))
+    }
 }
 
+/// Built with `new`, which caches the edges and plane; the vertices can't change afterwards.
 #[derive(Clone)]
 pub struct Parallelogram {
-    pub vertices: [ColumnVec<3>; 3],
+    vertices: [ColumnVec<3>; 3],
     pub material: Arc<dyn Material>,
+    edges: [ColumnVec<3>; 2],
+    normal: ColumnVec<3>,
 }
 
 impl Parallelogram {
+    /// The parallelogram with corner `vertices[0]` spanned by the edges to the other two vertices.
+    pub fn new(vertices: [ColumnVec<3>; 3], material: Arc<dyn Material>) -> Self {
+        let edges = [vertices[1] - vertices[0], vertices[2] - vertices[0]];
+        Self {
+            vertices,
+            material,
+            edges,
+            normal: edges[0].cross(edges[1]).normalized(),
+        }
+    }
+
+    pub fn vertices(&self) -> [ColumnVec<3>; 3] {
+        self.vertices
+    }
+
     pub fn normal(&self) -> ColumnVec<3> {
-        (self.vertices[1] - self.vertices[0])
-            .cross(self.vertices[2] - self.vertices[0])
-            .normalized()
+        self.normal
     }
 }
 
 impl Hittable for Parallelogram {
     fn hit(&self, ray: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        let new_ray = Ray {
-            origin: ray.origin - self.vertices[0],
-            direction: ray.direction,
-        };
-        let transform = Matrix::from_columns([
-            self.vertices[1] - self.vertices[0],
-            self.vertices[2] - self.vertices[0],
-            new_ray.direction,
-        ]);
-        let transform_inv = transform.inverse()?;
-        let new_ray = Ray {
-            origin: transform_inv * new_ray.origin,
-            direction: transform_inv * new_ray.direction,
-        };
-        if new_ray.origin[0] > 0.
-            && new_ray.origin[1] > 0.
-            && new_ray.origin[0] < 1.
-            && new_ray.origin[1] < 1.
-            && -new_ray.origin[2] > t_min
-            && -new_ray.origin[2] < t_max
-        {
-            Some(HitRecord::new(
-                ray,
-                self.normal(),
-                -new_ray.origin[2],
-                self.material.clone(),
-            ))
+        let [e1, e2] = self.edges;
+        let p = ray.direction.cross(e2);
+        let det = e1 * p;
+        if det == 0. {
+            return None;
+        }
+        let offset = ray.origin - self.vertices[0];
+        let u = offset * p / det;
+        if u <= 0. || u >= 1. {
+            return None;
+        }
+        let q = offset.cross(e1);
+        let v = ray.direction * q / det;
+        if v <= 0. || v >= 1. {
+            return None;
+        }
+        let t = e2 * q / det;
+        if t_min < t && t < t_max {
+            Some(HitRecord::new(ray, self.normal, t, self.material.clone()))
         } else {
             None
         }
     }
+
+    fn bounds(&self) -> Option<BoundingSphere> {
+        let [a, b, c] = self.vertices;
+        Some(BoundingSphere::from_points(&[a, b, c, b + c - a]))
+    }
+}
+
+/// Many spheres stored as separate coordinate arrays and tested in one tight loop,
+/// which is much faster than boxing each sphere into a `World` for sphere-heavy scenes.
+/// The index of the hit sphere is reported as the primitive id.
+#[derive(Clone, Default)]
+pub struct SphereList {
+    xs: Vec<f64>,
+    ys: Vec<f64>,
+    zs: Vec<f64>,
+    radii: Vec<f64>,
+    materials: Vec<Arc<dyn Material>>,
+}
+
+impl SphereList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, center: ColumnVec<3>, radius: f64, material: Arc<dyn Material>) {
+        self.xs.push(center[0]);
+        self.ys.push(center[1]);
+        self.zs.push(center[2]);
+        self.radii.push(radius);
+        self.materials.push(material);
+    }
+
+    fn center(&self, index: usize) -> ColumnVec<3> {
+        ColumnVec([self.xs[index], self.ys[index], self.zs[index]])
+    }
+}
+
+impl Hittable for SphereList {
+    fn hit(&self, ray: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let [ox, oy, oz] = ray.origin.0;
+        let [dx, dy, dz] = ray.direction.0;
+        let a = ray.direction * ray.direction;
+        let mut closest = t_max;
+        let mut hit = None;
+        let spheres = self.xs.iter().zip(&self.ys).zip(&self.zs).zip(&self.radii);
+        for (index, (((&x, &y), &z), &radius)) in spheres.enumerate() {
+            let (cx, cy, cz) = (ox - x, oy - y, oz - z);
+            let half_b = cx * dx + cy * dy + cz * dz;
+            let c = cx * cx + cy * cy + cz * cz - radius * radius;
+            let discrim = half_b * half_b - a * c;
+            if discrim < 0. {
+                continue;
+            }
+            let mut root = (-half_b - discrim.sqrt()) / a;
+            if root <= t_min {
+                root = (-half_b + discrim.sqrt()) / a;
+            }
+            if t_min < root && root < closest {
+                closest = root;
+                hit = Some(index);
+            }
+        }
+        let index = hit?;
+        Some(HitRecord {
+            primitive_id: index,
+            ..HitRecord::new(
+                ray,
+                (ray.at(closest) - self.center(index)).normalized(),
+                closest,
+                self.materials[index].clone(),
+            )
+        })
+    }
+
+    fn bounds(&self) -> Option<BoundingSphere> {
+        (0..self.radii.len())
+            .map(|index| BoundingSphere {
+                center: self.center(index),
+                radius: self.radii[index],
+            })
+            .reduce(BoundingSphere::union)
+    }
+
+    fn memory_size(&self) -> usize {
+        std::mem::size_of_val(self)
+            + (self.xs.capacity() + self.ys.capacity() + self.zs.capacity() + self.radii.capacity())
+                * std::mem::size_of::<f64>()
+            + self.materials.capacity() * std::mem::size_of::<Arc<dyn Material>>()
+    }
+}
+
+/// Replaces the material of every hit on `object`, so the same geometry can be rendered
+/// with different materials without rebuilding it.
+pub struct MaterialOverride {
+    pub object: Box<dyn Hittable>,
+    pub material: Arc<dyn Material>,
+}
+
+impl Hittable for MaterialOverride {
+    fn hit(&self, ray: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        self.object.hit(ray, t_min, t_max).map(|rec| HitRecord {
+            material: self.material.clone(),
+            ..rec
+        })
+    }
+
+    fn bounds(&self) -> Option<BoundingSphere> {
+        self.object.bounds()
+    }
+
+    fn memory_size(&self) -> usize {
+        std::mem::size_of_val(self) + self.object.memory_size()
+    }
+}
+
+/// Puts `object` on render layer `layer`.
+pub struct Layered {
+    pub object: Box<dyn Hittable>,
+    pub layer: u32,
+}
+
+impl Hittable for Layered {
+    fn hit(&self, ray: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        self.object.hit(ray, t_min, t_max)
+    }
+
+    fn bounds(&self) -> Option<BoundingSphere> {
+        self.object.bounds()
+    }
+
+    fn memory_size(&self) -> usize {
+        std::mem::size_of_val(self) + self.object.memory_size()
+    }
+
+    fn layer(&self) -> u32 {
+        self.layer
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct PickResult {
+    pub object_id: usize,
+    pub primitive_id: usize,
+    pub point: ColumnVec<3>,
+    pub normal: ColumnVec<3>,
+    pub t: f64,
+}
+
+/// Cuts away the part of `object` on the side of the plane that `normal` points to,
+/// filling the cut through closed solids with the `cap` material.
+/// Whether the cut is inside a solid is decided by whether the next surface behind it
+/// is hit from the inside, so objects need consistently outward facing normals.
+pub struct Section {
+    pub object: Box<dyn Hittable>,
+    pub point: ColumnVec<3>,
+    pub normal: ColumnVec<3>,
+    pub cap: Arc<dyn Material>,
+}
+
+impl Hittable for Section {
+    fn hit(&self, ray: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let speed = ray.direction * self.normal;
+        let distance = (ray.origin - self.point) * self.normal;
+        if speed == 0. {
+            return if distance > 0. {
+                None
+            } else {
+                self.object.hit(ray, t_min, t_max)
+            };
+        }
+        let t_plane = -distance / speed;
+        if speed > 0. {
+            return self.object.hit(ray, t_min, t_max.min(t_plane));
+        }
+        if t_plane <= t_min {
+            return self.object.hit(ray, t_min, t_max);
+        }
+        let behind = self.object.hit(ray, t_plane, f64::INFINITY);
+        match behind {
+            Some(rec) if !rec.front_face => (t_plane < t_max)
+                .then(|| HitRecord::new(ray, self.normal.normalized(), t_plane, self.cap.clone())),
+            Some(rec) if rec.t < t_max => Some(rec),
+            _ => None,
+        }
+    }
+
+    fn bounds(&self) -> Option<BoundingSphere> {
+        self.object.bounds()
+    }
+
+    fn memory_size(&self) -> usize {
+        std::mem::size_of_val(self) + self.object.memory_size()
+    }
 }
 
 pub struct World {
     pub objects: Vec<Box<dyn Hittable>>,
+    pub backgrounds: Backgrounds,
+    /// If set, objects on other layers are held out: the camera sees them as black, but they
+    /// still cover what is behind them and show up in reflections, shadows and indirect light,
+    /// so each layer can be rendered separately and composited.
+    pub active_layer: Option<u32>,
+    holdout: Arc<dyn Material>,
 }
 
 impl World {
     pub fn new() -> Self {
-        Self { objects: vec![] }
+        Self {
+            objects: vec![],
+            backgrounds: Backgrounds::default(),
+            active_layer: None,
+            holdout: Arc::new(Holdout),
+        }
     }
     pub fn push(&mut self, object: Box<dyn Hittable>) {
         self.objects.push(object)
     }
+
+    /// Adds objects produced by a parallel iterator, so generators of many primitives can
+    /// build the scene on all cores. Objects keep the order of the iterator.
+    pub fn par_extend<I>(&mut self, objects: I)
+    where
+        I: IntoParallelIterator<Item = Box<dyn Hittable>>,
+    {
+        self.objects.par_extend(objects)
+    }
+
+    /// Returns what is visible through the center of the pixel at `screen_x`, `screen_y`,
+    /// counted from the top left corner of the image like the rendered output.
+    /// Returns none for a pixel outside the image.
+    pub fn pick(&self, screen_x: u32, screen_y: u32, camera: &Camera) -> Option<PickResult> {
+        if screen_x >= camera.width || screen_y >= camera.height {
+            return None;
+        }
+        let u = (screen_x as f64 + 0.5) / (camera.width - 1) as f64;
+        let v = ((camera.height - screen_y) as f64 + 0.5) / (camera.height - 1) as f64;
+        let rec = self.hit(camera.pinhole_ray(u, v), 0., f64::INFINITY)?;
+        Some(PickResult {
+            object_id: rec.object_id,
+            primitive_id: rec.primitive_id,
+            point: rec.point,
+            normal: rec.normal,
+            t: rec.t,
+        })
+    }
+
+    /// Removes bounded objects that lie entirely outside the camera's view.
+    /// Culled objects no longer appear in reflections or cast shadows, so this is only
+    /// suitable when indirect visibility does not matter, such as previews.
+    pub fn cull(&mut self, camera: &Camera) {
+        self.objects.retain(|object| match object.bounds() {
+            Some(bounds) => camera.sees(bounds),
+            None => true,
+        })
+    }
 }
 
 impl Default for World {
@@ -191,12 +564,65 @@ impl Hittable for World {
     fn hit(&self, ray: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
         let mut result = None;
         let mut closest = t_max;
-        for object in &self.objects {
+        for (index, object) in self.objects.iter().enumerate() {
             if let Some(rec) = object.hit(ray, t_min, closest) {
-                result = Some(rec.clone());
                 closest = rec.t;
+                let held_out = ray.kind == RayKind::Camera
+                    && matches!(self.active_layer, Some(layer) if layer != object.layer());
+                result = Some(HitRecord {
+                    object_id: index,
+                    material: if held_out {
+                        self.holdout.clone()
+                    } else {
+                        rec.material
+                    },
+                    ..rec
+                });
             }
         }
         result
     }
+
+    fn bounds(&self) -> Option<BoundingSphere> {
+        let mut bounds = self.objects.iter().map(|object| object.bounds());
+        let first = bounds.next()??;
+        bounds.try_fold(first, |total, bounds| Some(total.union(bounds?)))
+    }
+
+    fn memory_size(&self) -> usize {
+        std::mem::size_of_val(self)
+            + self.objects.capacity() * std::mem::size_of::<Box<dyn Hittable>>()
+            + self
+                .objects
+                .iter()
+                .map(|object| object.memory_size())
+                .sum::<usize>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::{ColorType, Lambertian};
+
+    #[test]
+    fn pick_inside_and_outside_the_frame() {
+        let camera = Camera::new(80, 50, 2., 1., ColumnVec::zero());
+        let mut world = World::new();
+        world.push(Box::new(Sphere {
+            center: ColumnVec([0., 0., -5.]),
+            radius: 1.,
+            material: Arc::new(Lambertian {
+                color: ColorType::Solid(ColumnVec([0.5; 3])),
+            }),
+        }));
+
+        let pick = world.pick(40, 25, &camera).unwrap();
+        assert_eq!(pick.object_id, 0);
+        assert!((pick.t - 4.).abs() < 0.05);
+
+        assert!(world.pick(80, 25, &camera).is_none());
+        assert!(world.pick(40, 50, &camera).is_none());
+        assert!(world.pick(40, 60, &camera).is_none());
+    }
 }
@@ -0,0 +1,420 @@
+use std::sync::{Arc, Mutex, RwLock};
+
+use elgan_math::linalg::*;
+use image::{Rgb, RgbImage};
+
+use crate::{
+    hittable::{HitRecord, Hittable, World},
+    sampler,
+    stats::draw_text,
+    to_color, Ray, RayKind,
+};
+
+/// Closest distance along a secondary ray at which hits count. Secondary rays start from
+/// `HitRecord::spawn_point`, which already keeps them off the surface they leave, so this
+/// only needs to cover what that misses.
+pub const T_MIN: f64 = 1e-6;
+
+/// Computes how much light arrives along a ray, so the rendering algorithm can be swapped
+/// without changing how images are sampled and saved.
+pub trait Integrator: Sync {
+    /// Light arriving at the origin of `ray` from surfaces between `t_min` and `t_max`.
+    fn li(&self, ray: Ray, world: &World, t_min: f64, t_max: f64) -> ColumnVec<3>;
+
+    /// Called once a render has finished, to report anything noticed while tracing.
+    fn finish_render(&self) {}
+}
+
+/// What a path that runs out of bounces sees in place of the rest of the scene.
+#[derive(Clone, Copy, Default)]
+pub enum DepthFloor {
+    #[default]
+    Black,
+    /// The colors of the surfaces along the path, as though it then reached a white
+    /// environment. Keeps deep glass from turning black at modest depths.
+    Albedo,
+    /// A constant light, tinted by the surfaces along the path.
+    Ambient(ColumnVec<3>),
+}
+
+impl DepthFloor {
+    pub fn color(self) -> ColumnVec<3> {
+        match self {
+            DepthFloor::Black => ColumnVec::zero(),
+            DepthFloor::Albedo => ColumnVec([1.; 3]),
+            DepthFloor::Ambient(color) => color,
+        }
+    }
+}
+
+/// Paths a `PathTracer` ended for picking up a NaN or infinite value, and where the first of
+/// them came from. Printed once and cleared at the end of each render. Clones start empty.
+#[derive(Default)]
+pub struct NonFiniteReport {
+    paths: Mutex<(usize, Option<String>)>,
+}
+
+impl NonFiniteReport {
+    fn record(&self, origin: impl FnOnce() -> String) {
+        let mut paths = self.paths.lock().unwrap();
+        paths.0 += 1;
+        if paths.1.is_none() {
+            paths.1 = Some(origin());
+        }
+    }
+
+    fn print(&self) {
+        let (count, first) = std::mem::take(&mut *self.paths.lock().unwrap());
+        if let Some(first) = first {
+            println!(
+                "ended {} paths with non-finite values, the first {}",
+                count, first
+            );
+        }
+    }
+}
+
+impl Clone for NonFiniteReport {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+/// Traces one scattered ray per bounce, multiplying together the colors of every surface hit.
+#[derive(Clone)]
+pub struct PathTracer {
+    pub max_depth: i32,
+    /// Limits on how many bounces of each kind a path may take; a path is ended once any is
+    /// exceeded, so e.g. glass-in-glass recursion can be capped without losing diffuse light.
+    pub max_diffuse_depth: i32,
+    pub max_specular_depth: i32,
+    pub max_transmission_depth: i32,
+    /// After this many bounces, paths are randomly ended with a probability based on how much
+    /// light they can still carry, and survivors are weighted up to keep the image unbiased.
+    pub roulette_depth: Option<i32>,
+    pub depth_floor: DepthFloor,
+    /// End paths where they pick up a NaN or infinite value, keeping the light gathered
+    /// before it, and report how many there were and where the first came from.
+    pub report_non_finite: bool,
+    pub non_finite_report: NonFiniteReport,
+}
+
+impl Default for PathTracer {
+    fn default() -> Self {
+        Self {
+            max_depth: 16,
+            max_diffuse_depth: 16,
+            max_specular_depth: 16,
+            max_transmission_depth: 16,
+            roulette_depth: None,
+            depth_floor: DepthFloor::default(),
+            report_non_finite: false,
+            non_finite_report: NonFiniteReport::default(),
+        }
+    }
+}
+
+fn is_finite(vec: ColumnVec<3>) -> bool {
+    vec.0.iter().all(|x| x.is_finite())
+}
+
+impl Integrator for PathTracer {
+    fn li(&self, ray: Ray, world: &World, t_min: f64, t_max: f64) -> ColumnVec<3> {
+        let mut radiance = ColumnVec::zero();
+        let mut throughput = ColumnVec([1.; 3]);
+        let (mut ray, mut t_min, mut t_max) = (ray, t_min, t_max);
+        let mut bounces = [0; 4];
+        for depth in 0..self.max_depth {
+            let rec = match world.hit(ray, t_min, t_max) {
+                Some(rec) => rec,
+                None => return radiance + throughput.component_mul(world.backgrounds.color(ray)),
+            };
+            let finite_radiance = radiance;
+            radiance = radiance + throughput.component_mul(rec.material.emitted(ray, &rec));
+            let (object_id, primitive_id) = (rec.object_id, rec.primitive_id);
+            match rec.material.clone().scatter(ray, rec) {
+                (Some(scattered), Some(color)) => {
+                    throughput = throughput.component_mul(color);
+                    ray = scattered;
+                }
+                (None, Some(color)) => return radiance + throughput.component_mul(color),
+                _ => return radiance + throughput.component_mul(world.backgrounds.color(ray)),
+            }
+            if self.report_non_finite && !(is_finite(radiance) && is_finite(throughput)) {
+                self.non_finite_report.record(|| {
+                    format!(
+                        "at bounce {} on object {} primitive {}, scattered {:?} ray",
+                        depth, object_id, primitive_id, ray.kind
+                    )
+                });
+                return if is_finite(radiance) {
+                    radiance
+                } else {
+                    finite_radiance
+                };
+            }
+            let (kind, limit) = match ray.kind {
+                RayKind::Diffuse => (0, self.max_diffuse_depth),
+                RayKind::Specular => (1, self.max_specular_depth),
+                RayKind::Transmission => (2, self.max_transmission_depth),
+                RayKind::Camera => (3, self.max_depth),
+            };
+            bounces[kind] += 1;
+            if bounces[kind] > limit {
+                return radiance + throughput.component_mul(self.depth_floor.color());
+            }
+            if matches!(self.roulette_depth, Some(start) if depth >= start) {
+                let survival = throughput.0.into_iter().fold(0., f64::max).min(1.);
+                if sampler::random() >= survival {
+                    return radiance;
+                }
+                throughput = throughput / survival;
+            }
+            (t_min, t_max) = (T_MIN, f64::INFINITY);
+        }
+        radiance + throughput.component_mul(self.depth_floor.color())
+    }
+
+    fn finish_render(&self) {
+        self.non_finite_report.print();
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct PointLight {
+    pub position: ColumnVec<3>,
+    pub color: ColumnVec<3>,
+}
+
+/// Classic Whitted ray tracing: mirror and glass rays are all followed exactly and diffuse
+/// surfaces are lit only by `lights` and `ambient`, giving noise free images without
+/// indirect light. Materials that can't describe themselves this way are sampled as usual.
+#[derive(Clone)]
+pub struct Whitted {
+    pub lights: Vec<PointLight>,
+    pub ambient: ColumnVec<3>,
+    /// If above zero, `ambient` is darkened by how much of the hemisphere above each point
+    /// is blocked within this distance, giving cheap contact shadows.
+    pub occlusion_distance: f64,
+    pub occlusion_samples: u32,
+    pub max_depth: i32,
+    pub depth_floor: DepthFloor,
+}
+
+impl Whitted {
+    pub fn new(lights: Vec<PointLight>) -> Self {
+        Self {
+            lights,
+            ambient: ColumnVec::zero(),
+            occlusion_distance: 0.,
+            occlusion_samples: 8,
+            max_depth: 8,
+            depth_floor: DepthFloor::default(),
+        }
+    }
+
+    fn trace(&self, ray: Ray, world: &World, t_min: f64, t_max: f64, depth: i32) -> ColumnVec<3> {
+        if depth <= 0 {
+            return self.depth_floor.color();
+        }
+        let rec = match world.hit(ray, t_min, t_max) {
+            Some(rec) => rec,
+            None => return world.backgrounds.color(ray),
+        };
+        let material = rec.material.clone();
+        let emitted = material.emitted(ray, &rec);
+        if let Some(albedo) = material.albedo(&rec) {
+            let ambient = self.ambient * self.unoccluded(world, &rec);
+            return emitted + albedo.component_mul(ambient + self.direct(world, &rec));
+        }
+        let specular = material.specular_rays(ray, &rec);
+        if specular.is_empty() {
+            return emitted
+                + match material.scatter(ray, rec) {
+                    (Some(scattered), Some(color)) => color.component_mul(self.trace(
+                        scattered,
+                        world,
+                        T_MIN,
+                        f64::INFINITY,
+                        depth - 1,
+                    )),
+                    (None, Some(color)) => color,
+                    _ => world.backgrounds.color(ray),
+                };
+        }
+        specular
+            .into_iter()
+            .fold(emitted, |sum, (scattered, weight)| {
+                sum + weight.component_mul(self.trace(
+                    scattered,
+                    world,
+                    T_MIN,
+                    f64::INFINITY,
+                    depth - 1,
+                ))
+            })
+    }
+
+    /// Fraction of cosine weighted directions from the hit point that reach
+    /// `occlusion_distance` without hitting anything.
+    fn unoccluded(&self, world: &World, rec: &HitRecord) -> f64 {
+        if self.occlusion_distance <= 0. || self.occlusion_samples == 0 {
+            return 1.;
+        }
+        let open = (0..self.occlusion_samples)
+            .filter(|_| {
+                let direction =
+                    (rec.normal + sampler::random_inside_sphere().normalized()).normalized();
+                world
+                    .hit(
+                        Ray::new(rec.spawn_point(direction), direction),
+                        T_MIN,
+                        self.occlusion_distance,
+                    )
+                    .is_none()
+            })
+            .count();
+        open as f64 / self.occlusion_samples as f64
+    }
+
+    fn direct(&self, world: &World, rec: &HitRecord) -> ColumnVec<3> {
+        self.lights.iter().fold(ColumnVec::zero(), |sum, light| {
+            let to_light = light.position - rec.point;
+            let cos_theta = rec.normal * to_light.normalized();
+            if cos_theta <= 0.
+                || world
+                    .hit(Ray::new(rec.spawn_point(to_light), to_light), T_MIN, 1.)
+                    .is_some()
+            {
+                return sum;
+            }
+            sum + light.color * (cos_theta / (to_light * to_light))
+        })
+    }
+}
+
+impl Integrator for Whitted {
+    fn li(&self, ray: Ray, world: &World, t_min: f64, t_max: f64) -> ColumnVec<3> {
+        self.trace(ray, world, t_min, t_max, self.max_depth)
+    }
+}
+
+/// Shows each distinct material, or each type of material, in its own flat color, to check
+/// which materials are assigned where. Materials are numbered in the order they are first
+/// seen, which depends on thread timing, so use `legend` to see which color is which.
+#[derive(Default)]
+pub struct MaterialDebug {
+    /// Color by type of material rather than by material.
+    pub by_type: bool,
+    seen: RwLock<Vec<(usize, &'static str)>>,
+}
+
+impl MaterialDebug {
+    pub fn new(by_type: bool) -> Self {
+        Self {
+            by_type,
+            ..Self::default()
+        }
+    }
+
+    /// The color of the material numbered `index`, stepping around the hue circle by the
+    /// golden angle so consecutive materials are easy to tell apart.
+    fn color(index: usize) -> ColumnVec<3> {
+        let hue = (index as f64 * 0.618_033_988_749_895).fract() * 6.;
+        let x = 1. - (hue % 2. - 1.).abs();
+        let [r, g, b] = match hue as u32 {
+            0 => [1., x, 0.],
+            1 => [x, 1., 0.],
+            2 => [0., 1., x],
+            3 => [0., x, 1.],
+            4 => [x, 0., 1.],
+            _ => [1., 0., x],
+        };
+        ColumnVec([0.15 + 0.85 * r, 0.15 + 0.85 * g, 0.15 + 0.85 * b])
+    }
+
+    /// A swatch and name for every material seen so far.
+    pub fn legend(&self) -> RgbImage {
+        let seen = self.seen.read().unwrap();
+        let mut img = RgbImage::new(240, 24 * seen.len().max(1) as u32);
+        for (index, (_, name)) in seen.iter().enumerate() {
+            let top = 24 * index as u32;
+            let color = Self::color(index);
+            for y in top + 4..top + 20 {
+                for x in 4..20 {
+                    img.put_pixel(x, y, to_color(color));
+                }
+            }
+            draw_text(
+                &mut img,
+                28,
+                top + 7,
+                &format!("{} {}", index, name),
+                Rgb([255; 3]),
+            );
+        }
+        img
+    }
+}
+
+impl Integrator for MaterialDebug {
+    fn li(&self, ray: Ray, world: &World, t_min: f64, t_max: f64) -> ColumnVec<3> {
+        let rec = match world.hit(ray, t_min, t_max) {
+            Some(rec) => rec,
+            None => return ColumnVec::zero(),
+        };
+        let name = rec.material.type_name();
+        let id = Arc::as_ptr(&rec.material) as *const () as usize;
+        let find = |seen: &[(usize, &str)]| {
+            seen.iter().position(|&(seen_id, seen_name)| {
+                if self.by_type {
+                    seen_name == name
+                } else {
+                    seen_id == id
+                }
+            })
+        };
+        // Only new materials need the write lock, so threads don't queue up on every sample.
+        if let Some(index) = find(&self.seen.read().unwrap()) {
+            return Self::color(index);
+        }
+        let mut seen = self.seen.write().unwrap();
+        let index = match find(&seen) {
+            Some(index) => index,
+            None => {
+                seen.push((id, name));
+                seen.len() - 1
+            }
+        };
+        Self::color(index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        hittable::Sphere,
+        material::{ColorType, Lambertian},
+    };
+
+    #[test]
+    fn non_finite_path_keeps_finite_radiance() {
+        let mut world = World::new();
+        world.push(Box::new(Sphere {
+            center: ColumnVec([0., 0., -2.]),
+            radius: 1.,
+            material: Arc::new(Lambertian {
+                color: ColorType::Solid(ColumnVec([f64::NAN; 3])),
+            }),
+        }));
+        let integrator = PathTracer {
+            report_non_finite: true,
+            ..PathTracer::default()
+        };
+        let ray = Ray::new(ColumnVec::zero(), ColumnVec([0., 0., -1.]));
+        let radiance = integrator.li(ray, &world, T_MIN, f64::INFINITY);
+        assert!(radiance.close_enough(ColumnVec::zero()));
+        assert_eq!(integrator.non_finite_report.paths.lock().unwrap().0, 1);
+    }
+}
@@ -1,25 +1,72 @@
 use elgan_math::linalg::*;
 use image::Rgb;
+use material::MediumStack;
 
+pub mod animation;
+pub mod background;
+pub mod bench;
 pub mod camera;
+pub mod color;
+#[cfg(feature = "dashboard")]
+pub mod dashboard;
 pub mod hittable;
+pub mod integrator;
 pub mod material;
+pub mod noise;
+pub mod regression;
 pub mod render;
+pub mod sampler;
+pub mod scenes;
+pub mod snapshot;
+pub mod stats;
 
 pub fn to_color(vec: ColumnVec<3>) -> Rgb<u8> {
     //Rgb(vec.0.map(|i: f64| (i.clamp(0., 1.) * 255.) as u8))
     Rgb(vec.0.map(|i: f64| (i.clamp(0., 1.).sqrt() * 255.) as u8))
 }
 
+const BAYER: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+pub(crate) fn dither_threshold(x: u32, y: u32) -> f64 {
+    (BAYER[y as usize % 4][x as usize % 4] as f64 + 0.5) / 16.
+}
+
+/// Like `to_color`, but adds an ordered dither threshold based on the pixel position
+/// so smooth gradients like the sky don't band when quantized to 8 bits.
+pub fn to_color_dithered(vec: ColumnVec<3>, x: u32, y: u32) -> Rgb<u8> {
+    let threshold = dither_threshold(x, y);
+    Rgb(vec
+        .0
+        .map(|i: f64| (i.clamp(0., 1.).sqrt() * 255. + threshold).min(255.) as u8))
+}
+
+/// How a ray came to be traced.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RayKind {
+    Camera,
+    /// Mirror-like or glossy reflection.
+    Specular,
+    /// Refraction into or out of a transparent material.
+    Transmission,
+    Diffuse,
+}
+
 #[derive(Clone, Copy)]
 pub struct Ray {
     pub origin: ColumnVec<3>,
     pub direction: ColumnVec<3>,
+    pub media: MediumStack,
+    pub kind: RayKind,
 }
 
 impl Ray {
     pub fn new(origin: ColumnVec<3>, direction: ColumnVec<3>) -> Self {
-        Self { origin, direction }
+        Self {
+            origin,
+            direction,
+            media: MediumStack::default(),
+            kind: RayKind::Camera,
+        }
     }
 
     pub fn at(self, t: f64) -> ColumnVec<3> {
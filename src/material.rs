@@ -1,9 +1,37 @@
+use std::{f64::consts::PI, sync::Arc};
+
 use elgan_math::linalg::*;
 
-use crate::{hittable::HitRecord, Ray};
+use crate::{
+    background::Background, hittable::HitRecord, noise::perlin_gradient, sampler, Ray, RayKind,
+};
 
 pub trait Material: Send + Sync {
     fn scatter(&self, ray: Ray, rec: HitRecord) -> (Option<Ray>, Option<ColumnVec<3>>);
+
+    /// Light added on top of whatever the scattered ray brings back.
+    fn emitted(&self, _ray: Ray, _rec: &HitRecord) -> ColumnVec<3> {
+        ColumnVec::zero()
+    }
+
+    /// Color of a diffuse surface, for integrators that light diffuse surfaces directly.
+    /// None if the surface is not diffuse.
+    fn albedo(&self, _rec: &HitRecord) -> Option<ColumnVec<3>> {
+        None
+    }
+
+    /// Every ideal mirror or refraction ray leaving the surface with its weight,
+    /// for integrators that follow them all instead of sampling one.
+    /// Empty if the surface has no such description.
+    fn specular_rays(&self, _ray: Ray, _rec: &HitRecord) -> Vec<(Ray, ColumnVec<3>)> {
+        vec![]
+    }
+
+    /// Name of the material's type, for debugging output.
+    fn type_name(&self) -> &'static str {
+        let name = std::any::type_name::<Self>();
+        name.rsplit("::").next().unwrap_or(name)
+    }
 }
 
 #[derive(Clone, Copy)]
@@ -40,19 +68,27 @@ pub struct Lambertian {
 }
 
 impl Material for Lambertian {
-    fn scatter(&self, _ray: Ray, rec: HitRecord) -> (Option<Ray>, Option<ColumnVec<3>>) {
-        let mut scatter_direction = ColumnVec::random_in_hemisphere(rec.normal);
+    fn scatter(&self, ray: Ray, rec: HitRecord) -> (Option<Ray>, Option<ColumnVec<3>>) {
+        // A point on the unit sphere tangent to the surface gives directions with a pdf of
+        // cos(theta) / pi, which cancels the cosine and 1 / pi of the BRDF, leaving just the color.
+        let mut scatter_direction = rec.normal + sampler::random_inside_sphere().normalized();
         if scatter_direction.close_enough(ColumnVec::zero()) {
             scatter_direction = rec.normal
         }
         (
             Some(Ray {
-                origin: rec.point,
+                origin: rec.spawn_point(scatter_direction),
                 direction: scatter_direction,
+                kind: RayKind::Diffuse,
+                ..ray
             }),
             Some(self.color.color(rec)),
         )
     }
+
+    fn albedo(&self, rec: &HitRecord) -> Option<ColumnVec<3>> {
+        Some(self.color.color(rec.clone()))
+    }
 }
 
 #[derive(Clone, Copy)]
@@ -61,17 +97,49 @@ pub struct Metal {
     pub fuzz: f64,
 }
 
+impl Metal {
+    /// Builds a metal from perceptual roughness in [0, 1], remapped to fuzz as roughness²
+    /// the way Blender and Disney-style roughness sliders behave.
+    pub fn with_roughness(color: ColorType, roughness: f64) -> Self {
+        let roughness = roughness.clamp(0., 1.);
+        Self {
+            color,
+            fuzz: roughness * roughness,
+        }
+    }
+
+    pub fn roughness(&self) -> f64 {
+        self.fuzz.sqrt()
+    }
+}
+
 impl Material for Metal {
     fn scatter(&self, ray: Ray, rec: HitRecord) -> (Option<Ray>, Option<ColumnVec<3>>) {
         let reflected = Matrix::reflection_normal_vec(rec.normal) * ray.direction.normalized();
+        let direction = reflected + sampler::random_inside_sphere() * self.fuzz;
         (
             Some(Ray {
-                origin: rec.point,
-                direction: reflected + ColumnVec::random_inside_sphere() * self.fuzz,
+                origin: rec.spawn_point(direction),
+                direction,
+                kind: RayKind::Specular,
+                ..ray
             }),
             Some(self.color.color(rec)),
         )
     }
+
+    fn specular_rays(&self, ray: Ray, rec: &HitRecord) -> Vec<(Ray, ColumnVec<3>)> {
+        let direction = reflect(ray.direction, rec.normal);
+        vec![(
+            Ray {
+                origin: rec.spawn_point(direction),
+                direction,
+                kind: RayKind::Specular,
+                ..ray
+            },
+            self.color.color(rec.clone()),
+        )]
+    }
 }
 
 fn refract(vec: ColumnVec<3>, normal: ColumnVec<3>, ratio: f64) -> ColumnVec<3> {
@@ -87,49 +155,602 @@ fn reflect(vec: ColumnVec<3>, normal: ColumnVec<3>) -> ColumnVec<3> {
     vec - 2. * (vec * normal) * normal
 }
 
+/// Mirror reflection about the shading normal, or about the geometric normal if a perturbed
+/// shading normal would send it below the surface.
+fn reflect_above(direction: ColumnVec<3>, rec: &HitRecord) -> ColumnVec<3> {
+    let reflected = reflect(direction, rec.normal);
+    if reflected * rec.geometric_normal > 0. {
+        reflected
+    } else {
+        reflect(direction, rec.geometric_normal)
+    }
+}
+
 fn refelctance(cosine: f64, ref_index: f64) -> f64 {
     let r0 = (1. - ref_index) / (1. + ref_index);
     let r0 = r0 * r0;
     r0 + (1. - r0) * (1. - cosine).powi(5)
 }
 
+pub const MAX_NESTED_MEDIA: usize = 4;
+
+/// The dielectrics a ray is currently inside, innermost last.
+/// Media are identified by the address of their material so that two objects
+/// sharing one material count as the same volume.
+/// Stored inline in every `Ray`, so it holds at most `MAX_NESTED_MEDIA` (4) media. Entering
+/// more is a debug assertion failure; release builds ignore the extra media, so refraction
+/// inside them uses the wrong index.
+#[derive(Clone, Copy, Default)]
+pub struct MediumStack {
+    media: [(usize, f64, u32); MAX_NESTED_MEDIA],
+    len: usize,
+}
+
+impl MediumStack {
+    /// The id and refractive index of the medium the ray is travelling through: the one with
+    /// the highest priority, or the innermost of those tied. Air, with id 0 and index 1, if the
+    /// ray is outside everything.
+    pub fn current(&self) -> (usize, f64) {
+        self.media[..self.len]
+            .iter()
+            .max_by_key(|&&(_, _, priority)| priority)
+            .map_or((0, 1.), |&(id, ir, _)| (id, ir))
+    }
+
+    pub fn current_ir(&self) -> f64 {
+        self.current().1
+    }
+
+    /// Pushes a medium; once the stack is full further media are not tracked.
+    pub fn push(&mut self, id: usize, ir: f64, priority: u32) {
+        debug_assert!(
+            self.len < MAX_NESTED_MEDIA,
+            "more than {} nested media",
+            MAX_NESTED_MEDIA
+        );
+        if self.len < MAX_NESTED_MEDIA {
+            self.media[self.len] = (id, ir, priority);
+            self.len += 1;
+        }
+    }
+
+    /// Removes the innermost entry for the medium, if the ray was inside it.
+    pub fn remove(&mut self, id: usize) {
+        if let Some(index) = self.media[..self.len]
+            .iter()
+            .rposition(|&(i, _, _)| i == id)
+        {
+            self.media.copy_within(index + 1..self.len, index);
+            self.len -= 1;
+        }
+    }
+}
+
 #[derive(Clone, Copy)]
 pub struct Dielectric {
     pub ir: f64,
     pub color: ColorType,
+    /// Where volumes overlap, the one with the highest priority fills the overlap and the
+    /// surfaces of the others inside it are ignored. Lets e.g. a liquid be modeled slightly
+    /// overlapping its glass (with the glass at a higher priority) without seams.
+    /// With equal priorities the innermost volume wins.
+    pub priority: u32,
+}
+
+impl Dielectric {
+    /// The ratio of refractive indices across the surface, and the media the ray is in once
+    /// through it. The ratio is none if the surface doesn't change which medium the ray is
+    /// in, because it lies inside a higher priority volume, and should be passed straight
+    /// through.
+    fn interface(&self, ray: Ray, rec: &HitRecord) -> (Option<f64>, MediumStack) {
+        let id = self as *const Self as usize;
+        let mut media = ray.media;
+        let (before, before_ir) = media.current();
+        if rec.front_face {
+            media.push(id, self.ir, self.priority);
+        } else {
+            media.remove(id);
+        }
+        let (after, after_ir) = media.current();
+        if before == after {
+            (None, media)
+        } else {
+            (Some(before_ir / after_ir), media)
+        }
+    }
+
+    /// A ray continuing through an ignored surface.
+    fn pass_through(ray: Ray, rec: &HitRecord, media: MediumStack) -> Ray {
+        Ray {
+            origin: rec.spawn_point(ray.direction),
+            media,
+            ..ray
+        }
+    }
 }
 
 impl Material for Dielectric {
     fn scatter(&self, ray: Ray, rec: HitRecord) -> (Option<Ray>, Option<ColumnVec<3>>) {
-        let ratio = if rec.front_face {
-            1. / self.ir
-        } else {
-            self.ir
+        let (ratio, media) = match self.interface(ray, &rec) {
+            (Some(ratio), media) => (ratio, media),
+            (None, media) => {
+                return (
+                    Some(Self::pass_through(ray, &rec, media)),
+                    Some(ColumnVec([1.; 3])),
+                )
+            }
         };
-        let cos_theta = -(ray.direction.normalized() * rec.normal);
+        let cos_theta = -(ray.direction.normalized() * rec.normal).clamp(-1., 0.);
         let sin_theta = (1. - cos_theta * cos_theta).sqrt();
-        let refracted = if ratio * sin_theta > 1. || refelctance(cos_theta, ratio) > rand::random()
+        let refracted = refract(ray.direction, rec.normal, ratio);
+        let (refracted, media, kind) = if ratio * sin_theta > 1.
+            || refracted * rec.geometric_normal >= 0.
+            || refelctance(cos_theta, ratio) > sampler::random()
         {
-            reflect(ray.direction, rec.normal)
+            (
+                reflect_above(ray.direction, &rec),
+                ray.media,
+                RayKind::Specular,
+            )
         } else {
-            refract(ray.direction, rec.normal, ratio)
+            (refracted, media, RayKind::Transmission)
         };
         (
             Some(Ray {
-                origin: rec.point,
+                origin: rec.spawn_point(refracted),
                 direction: refracted,
+                media,
+                kind,
             }),
             Some(self.color.color(rec)),
         )
     }
+
+    fn specular_rays(&self, ray: Ray, rec: &HitRecord) -> Vec<(Ray, ColumnVec<3>)> {
+        let (ratio, media) = match self.interface(ray, rec) {
+            (Some(ratio), media) => (ratio, media),
+            (None, media) => {
+                return vec![(Self::pass_through(ray, rec, media), ColumnVec([1.; 3]))]
+            }
+        };
+        let color = self.color.color(rec.clone());
+        let cos_theta = -(ray.direction.normalized() * rec.normal).clamp(-1., 0.);
+        let sin_theta = (1. - cos_theta * cos_theta).sqrt();
+        let direction = reflect_above(ray.direction, rec);
+        let reflected = Ray {
+            origin: rec.spawn_point(direction),
+            direction,
+            kind: RayKind::Specular,
+            ..ray
+        };
+        let refracted = refract(ray.direction, rec.normal, ratio);
+        if ratio * sin_theta > 1. || refracted * rec.geometric_normal >= 0. {
+            return vec![(reflected, color)];
+        }
+        let reflectance = refelctance(cos_theta, ratio);
+        vec![
+            (reflected, color * reflectance),
+            (
+                Ray {
+                    origin: rec.spawn_point(refracted),
+                    direction: refracted,
+                    media,
+                    kind: RayKind::Transmission,
+                },
+                color * (1. - reflectance),
+            ),
+        ]
+    }
 }
 
 pub struct Emissive {
     pub color: ColorType,
 }
 
+impl Emissive {
+    /// An incandescent emitter at the given temperature in kelvin,
+    /// with its brightness set by `intensity` (the luminance of the color).
+    pub fn blackbody(kelvin: f64, intensity: f64) -> Self {
+        Self {
+            color: ColorType::Solid(blackbody_color(kelvin) * intensity),
+        }
+    }
+}
+
+/// Linear sRGB color of a black body at the given temperature, normalized to unit luminance.
+/// Planck's law is integrated against the analytic CIE 1931 fit of Wyman, Sloan and Shirley.
+/// Black for temperatures too low to give off visible light, including zero and below.
+pub fn blackbody_color(kelvin: f64) -> ColumnVec<3> {
+    if kelvin <= 0. || kelvin.is_nan() {
+        return ColumnVec::zero();
+    }
+    let lobe = |x: f64, mu: f64, below: f64, above: f64| {
+        let t = (x - mu) / if x < mu { below } else { above };
+        (-0.5 * t * t).exp()
+    };
+    let mut xyz = ColumnVec::zero();
+    for nm in (380..=780).step_by(5) {
+        let nm = nm as f64;
+        let meters = nm * 1e-9;
+        let radiance = 1. / (meters.powi(5) * ((1.438776877e-2 / (meters * kelvin)).exp() - 1.));
+        let matching = ColumnVec([
+            1.056 * lobe(nm, 599.8, 37.9, 31.0) + 0.362 * lobe(nm, 442.0, 16.0, 26.7)
+                - 0.065 * lobe(nm, 501.1, 20.4, 26.2),
+            0.821 * lobe(nm, 568.8, 46.9, 40.5) + 0.286 * lobe(nm, 530.9, 16.3, 31.1),
+            1.217 * lobe(nm, 437.0, 11.8, 36.0) + 0.681 * lobe(nm, 459.0, 26.0, 13.8),
+        ]);
+        xyz = xyz + matching * radiance;
+    }
+    // too cold for any wavelength to register, or too hot to represent
+    if xyz[1] <= 0. || !xyz[1].is_finite() {
+        return ColumnVec::zero();
+    }
+    let xyz = xyz / xyz[1];
+    let rgb = [
+        3.2406 * xyz[0] - 1.5372 * xyz[1] - 0.4986 * xyz[2],
+        -0.9689 * xyz[0] + 1.8758 * xyz[1] + 0.0415 * xyz[2],
+        0.0557 * xyz[0] - 0.2040 * xyz[1] + 1.0570 * xyz[2],
+    ];
+    ColumnVec(rgb.map(|x| x.max(0.)))
+}
+
 impl Material for Emissive {
     fn scatter(&self, _ray: Ray, rec: HitRecord) -> (Option<Ray>, Option<ColumnVec<3>>) {
         (None, Some(self.color.color(rec)))
     }
 }
+
+/// Black and stops every path. Used in place of objects on other render layers, which are
+/// hidden from the camera but still cover whatever is behind them.
+pub struct Holdout;
+
+impl Material for Holdout {
+    fn scatter(&self, _ray: Ray, _rec: HitRecord) -> (Option<Ray>, Option<ColumnVec<3>>) {
+        (None, Some(ColumnVec::zero()))
+    }
+}
+
+/// Looks up `environment` in the reflected direction instead of tracing a secondary ray.
+/// Cheap stand-in for Metal in preview renders; other objects do not show up in the reflection.
+pub struct ReflectionProbe {
+    pub color: ColorType,
+    /// Usually the world's `backgrounds.specular`, what a traced reflection would see.
+    pub environment: Background,
+}
+
+impl Material for ReflectionProbe {
+    fn scatter(&self, ray: Ray, rec: HitRecord) -> (Option<Ray>, Option<ColumnVec<3>>) {
+        let reflected = reflect(ray.direction, rec.normal);
+        (
+            None,
+            Some(
+                self.color
+                    .color(rec)
+                    .component_mul(self.environment.color(reflected)),
+            ),
+        )
+    }
+}
+
+/// Emitter whose radiance falls off as cos^exponent away from the surface normal.
+/// An exponent of 0 gives a uniform panel; one-sided panels are black from behind.
+pub struct DirectionalEmissive {
+    pub color: ColorType,
+    pub exponent: f64,
+    pub two_sided: bool,
+}
+
+impl Material for DirectionalEmissive {
+    fn scatter(&self, ray: Ray, rec: HitRecord) -> (Option<Ray>, Option<ColumnVec<3>>) {
+        if !rec.front_face && !self.two_sided {
+            return (None, Some(ColumnVec::zero()));
+        }
+        let cos_theta = -(ray.direction.normalized() * rec.normal);
+        (
+            None,
+            Some(self.color.color(rec) * cos_theta.max(0.).powf(self.exponent)),
+        )
+    }
+}
+
+/// Perturbs the shading normal with animated gradient noise before handing the hit to `base`.
+/// Meant for water surfaces and rough ground; `time` drifts the pattern between frames.
+pub struct NoiseBump {
+    pub base: Arc<dyn Material>,
+    pub scale: f64,
+    pub amplitude: f64,
+    pub time: f64,
+}
+
+impl NoiseBump {
+    fn perturb(&self, rec: &HitRecord) -> HitRecord {
+        let p = rec.point * self.scale + ColumnVec([self.time, 0.3 * self.time, self.time]);
+        let gradient = perlin_gradient(p);
+        let tangential = gradient - (gradient * rec.normal) * rec.normal;
+        let normal = (rec.normal - self.amplitude * tangential).normalized();
+        HitRecord {
+            normal,
+            ..rec.clone()
+        }
+    }
+}
+
+impl Material for NoiseBump {
+    fn scatter(&self, ray: Ray, rec: HitRecord) -> (Option<Ray>, Option<ColumnVec<3>>) {
+        let (scattered, color) = self.base.scatter(ray, self.perturb(&rec));
+        // Directions picked around the bumped normal can point into the real surface;
+        // mirror reflected ones back out rather than let them hit it from inside.
+        let scattered = scattered.map(|scattered| {
+            let below = scattered.direction * rec.geometric_normal;
+            if scattered.kind == RayKind::Transmission || below >= 0. {
+                return scattered;
+            }
+            let direction = scattered.direction - 2. * below * rec.geometric_normal;
+            Ray {
+                origin: rec.spawn_point(direction),
+                direction,
+                ..scattered
+            }
+        });
+        (scattered, color)
+    }
+
+    fn emitted(&self, ray: Ray, rec: &HitRecord) -> ColumnVec<3> {
+        self.base.emitted(ray, &self.perturb(rec))
+    }
+
+    fn albedo(&self, rec: &HitRecord) -> Option<ColumnVec<3>> {
+        self.base.albedo(&self.perturb(rec))
+    }
+
+    fn specular_rays(&self, ray: Ray, rec: &HitRecord) -> Vec<(Ray, ColumnVec<3>)> {
+        self.base.specular_rays(ray, &self.perturb(rec))
+    }
+}
+
+const VIEW_SAMPLES: u32 = 16;
+const SUN_SAMPLES: u32 = 8;
+
+/// Single scattering atmosphere (Rayleigh and Mie) around a planet, meant as the material of
+/// a sphere of radius `top` around `center`. Rays pass straight through it, dimmed by the air
+/// they cross and brightened by sunlight scattered towards them.
+/// Coefficients are per scene unit and heights are in scene units.
+#[derive(Clone, Copy)]
+pub struct Atmosphere {
+    pub center: ColumnVec<3>,
+    pub planet_radius: f64,
+    pub top: f64,
+    pub sun_direction: ColumnVec<3>,
+    pub sun_color: ColumnVec<3>,
+    pub rayleigh: ColumnVec<3>,
+    pub rayleigh_height: f64,
+    pub mie: f64,
+    pub mie_height: f64,
+    /// Henyey-Greenstein asymmetry of Mie scattering, 0.76 for Earth.
+    pub mie_g: f64,
+}
+
+impl Atmosphere {
+    /// Earth's atmosphere scaled to a planet of the given radius.
+    pub fn earth_like(
+        center: ColumnVec<3>,
+        planet_radius: f64,
+        sun_direction: ColumnVec<3>,
+        sun_color: ColumnVec<3>,
+    ) -> Self {
+        let meters = 6371e3 / planet_radius;
+        Self {
+            center,
+            planet_radius,
+            top: planet_radius * (6431e3 / 6371e3),
+            sun_direction: sun_direction.normalized(),
+            sun_color,
+            rayleigh: ColumnVec([5.8e-6, 13.5e-6, 33.1e-6]) * meters,
+            rayleigh_height: 8e3 / meters,
+            mie: 21e-6 * meters,
+            mie_height: 1.2e3 / meters,
+            mie_g: 0.76,
+        }
+    }
+
+    /// Distances along a normalized direction to where it enters and leaves a sphere around the center.
+    fn sphere_crossing(
+        &self,
+        origin: ColumnVec<3>,
+        direction: ColumnVec<3>,
+        radius: f64,
+    ) -> Option<(f64, f64)> {
+        let oc = origin - self.center;
+        let half_b = oc * direction;
+        let discrim = half_b * half_b - (oc * oc - radius * radius);
+        if discrim < 0. {
+            return None;
+        }
+        Some((-half_b - discrim.sqrt(), -half_b + discrim.sqrt()))
+    }
+
+    /// Rayleigh and Mie densities relative to sea level.
+    fn density(&self, point: ColumnVec<3>) -> (f64, f64) {
+        let height = (point - self.center).length() - self.planet_radius;
+        (
+            (-height / self.rayleigh_height).exp(),
+            (-height / self.mie_height).exp(),
+        )
+    }
+
+    fn extinction(&self, depth: (f64, f64)) -> ColumnVec<3> {
+        let optical = self.rayleigh * depth.0 + ColumnVec([1.1 * self.mie * depth.1; 3]);
+        ColumnVec(optical.0.map(|x| (-x).exp()))
+    }
+
+    /// Light scattered towards the viewer along the segment, and how much of what lies
+    /// behind the segment gets through it.
+    fn integrate(
+        &self,
+        origin: ColumnVec<3>,
+        direction: ColumnVec<3>,
+        length: f64,
+    ) -> (ColumnVec<3>, ColumnVec<3>) {
+        let step = length / VIEW_SAMPLES as f64;
+        let mu = direction * self.sun_direction;
+        let rayleigh_phase = 3. / (16. * PI) * (1. + mu * mu);
+        let g = self.mie_g;
+        let mie_phase = 3. / (8. * PI) * ((1. - g * g) * (1. + mu * mu))
+            / ((2. + g * g) * (1. + g * g - 2. * g * mu).powf(1.5));
+        let mut view_depth = (0., 0.);
+        let mut rayleigh_sum = ColumnVec::zero();
+        let mut mie_sum = ColumnVec::zero();
+        for i in 0..VIEW_SAMPLES {
+            let point = origin + (i as f64 + 0.5) * step * direction;
+            let (rayleigh, mie) = self.density(point);
+            view_depth = (view_depth.0 + rayleigh * step, view_depth.1 + mie * step);
+            if matches!(
+                self.sphere_crossing(point, self.sun_direction, self.planet_radius),
+                Some((_, exit)) if exit > 0.
+            ) {
+                continue;
+            }
+            let sun_length = match self.sphere_crossing(point, self.sun_direction, self.top) {
+                Some((_, exit)) => exit,
+                None => continue,
+            };
+            let sun_step = sun_length / SUN_SAMPLES as f64;
+            let mut sun_depth = (0., 0.);
+            for j in 0..SUN_SAMPLES {
+                let (r, m) = self.density(point + (j as f64 + 0.5) * sun_step * self.sun_direction);
+                sun_depth = (sun_depth.0 + r * sun_step, sun_depth.1 + m * sun_step);
+            }
+            let attenuation =
+                self.extinction((view_depth.0 + sun_depth.0, view_depth.1 + sun_depth.1));
+            rayleigh_sum = rayleigh_sum + attenuation * (rayleigh * step);
+            mie_sum = mie_sum + attenuation * (mie * step);
+        }
+        let inscattered = self.rayleigh.component_mul(rayleigh_sum) * rayleigh_phase
+            + mie_sum * (self.mie * mie_phase);
+        (
+            self.sun_color.component_mul(inscattered),
+            self.extinction(view_depth),
+        )
+    }
+
+    /// The stretch of air the ray crosses before its next hit on this atmosphere or the planet.
+    fn segment(&self, ray: Ray, rec: &HitRecord) -> Option<(ColumnVec<3>, ColumnVec<3>, f64)> {
+        let direction = ray.direction.normalized();
+        if rec.front_face {
+            let (_, exit) = self.sphere_crossing(rec.point, direction, self.top)?;
+            let end = match self.sphere_crossing(rec.point, direction, self.planet_radius) {
+                Some((ground, _)) if ground > 0. => ground,
+                _ => exit,
+            };
+            Some((rec.point, direction, end))
+        } else if (ray.origin - self.center).length() < self.top - rec.error {
+            Some((ray.origin, direction, (rec.point - ray.origin).length()))
+        } else {
+            // the air was already accounted for when this ray entered the atmosphere
+            None
+        }
+    }
+}
+
+impl Material for Atmosphere {
+    fn scatter(&self, ray: Ray, rec: HitRecord) -> (Option<Ray>, Option<ColumnVec<3>>) {
+        let transmittance = match self.segment(ray, &rec) {
+            Some((origin, direction, length)) => self.integrate(origin, direction, length).1,
+            None => ColumnVec([1.; 3]),
+        };
+        // A ray entering continues from exactly the hit point, so when it leaves `segment`
+        // can tell it was already accounted for rather than starting inside the air.
+        let origin = if rec.front_face {
+            rec.point
+        } else {
+            rec.spawn_point(ray.direction)
+        };
+        (Some(Ray { origin, ..ray }), Some(transmittance))
+    }
+
+    fn emitted(&self, ray: Ray, rec: &HitRecord) -> ColumnVec<3> {
+        match self.segment(ray, rec) {
+            Some((origin, direction, length)) => self.integrate(origin, direction, length).0,
+            None => ColumnVec::zero(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable::{Hittable, Sphere};
+
+    #[test]
+    fn blackbody_is_black_without_temperature() {
+        for kelvin in [-100., 0., 1., f64::NAN] {
+            assert!(blackbody_color(kelvin).close_enough(ColumnVec::zero()));
+        }
+        assert!(blackbody_color(6500.)
+            .0
+            .iter()
+            .all(|x| x.is_finite() && *x > 0.));
+    }
+
+    #[test]
+    fn bumped_diffuse_rays_stay_above_the_surface() {
+        let bumped = NoiseBump {
+            base: Arc::new(Lambertian {
+                color: ColorType::Solid(ColumnVec([0.5; 3])),
+            }),
+            scale: 3.,
+            amplitude: 4.,
+            time: 0.,
+        };
+        let sphere = Sphere {
+            center: ColumnVec::zero(),
+            radius: 1.,
+            material: Arc::new(Holdout),
+        };
+        for i in 0..1000 {
+            let angle = i as f64 * 0.01;
+            let ray = Ray::new(
+                ColumnVec([angle.cos() * 0.5, angle.sin() * 0.5, 5.]),
+                ColumnVec([0., 0., -1.]),
+            );
+            let rec = sphere.hit(ray, 1e-6, f64::INFINITY).unwrap();
+            let (scattered, _) = bumped.scatter(ray, rec.clone());
+            assert!(scattered.unwrap().direction * rec.geometric_normal >= 0.);
+        }
+    }
+
+    #[test]
+    fn atmosphere_counts_one_pass_once() {
+        let atmosphere = Atmosphere::earth_like(
+            ColumnVec::zero(),
+            1.,
+            ColumnVec([0., 1., 0.]),
+            ColumnVec([1.; 3]),
+        );
+        let shell = Sphere {
+            center: ColumnVec::zero(),
+            radius: atmosphere.top,
+            material: Arc::new(atmosphere),
+        };
+        // passes above the planet, through the air only
+        let ray = Ray::new(ColumnVec([-5., 1.005, 0.]), ColumnVec([1., 0., 0.]));
+
+        let mut radiance = ColumnVec::zero();
+        let mut throughput = ColumnVec([1.; 3]);
+        let mut current = ray;
+        while let Some(rec) = shell.hit(current, 1e-6, f64::INFINITY) {
+            radiance = radiance + throughput.component_mul(atmosphere.emitted(current, &rec));
+            let (next, attenuation) = atmosphere.scatter(current, rec);
+            throughput = throughput.component_mul(attenuation.unwrap());
+            current = next.unwrap();
+        }
+
+        let (entry, exit) = atmosphere
+            .sphere_crossing(ray.origin, ray.direction, atmosphere.top)
+            .unwrap();
+        let (inscattered, transmittance) =
+            atmosphere.integrate(ray.at(entry), ray.direction, exit - entry);
+        assert!((radiance - inscattered).length() < 1e-6 * inscattered.length());
+        assert!((throughput - transmittance).length() < 1e-6);
+    }
+}
@@ -0,0 +1,78 @@
+use elgan_math::linalg::*;
+
+const GRADIENTS: [[f64; 3]; 12] = [
+    [1., 1., 0.],
+    [-1., 1., 0.],
+    [1., -1., 0.],
+    [-1., -1., 0.],
+    [1., 0., 1.],
+    [-1., 0., 1.],
+    [1., 0., -1.],
+    [-1., 0., -1.],
+    [0., 1., 1.],
+    [0., -1., 1.],
+    [0., 1., -1.],
+    [0., -1., -1.],
+];
+
+fn hash(x: i64, y: i64, z: i64) -> u64 {
+    let mut h = (x as u64).wrapping_mul(0x9e3779b97f4a7c15)
+        ^ (y as u64).wrapping_mul(0xc2b2ae3d27d4eb4f)
+        ^ (z as u64).wrapping_mul(0x165667b19e3779f9);
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xff51afd7ed558ccd);
+    h ^ (h >> 33)
+}
+
+/// The PCG hash of Jarzynski and Olano, a cheap well mixed 32 bit hash.
+pub fn pcg_hash(input: u32) -> u32 {
+    let state = input.wrapping_mul(747796405).wrapping_add(2891336453);
+    let word = ((state >> ((state >> 28) + 4)) ^ state).wrapping_mul(277803737);
+    (word >> 22) ^ word
+}
+
+fn fade(t: f64) -> f64 {
+    t * t * t * (t * (t * 6. - 15.) + 10.)
+}
+
+/// Gradient noise in roughly [-1, 1], smooth and zero at integer lattice points.
+pub fn perlin(p: ColumnVec<3>) -> f64 {
+    let cell = p.0.map(f64::floor);
+    let frac = [p[0] - cell[0], p[1] - cell[1], p[2] - cell[2]];
+    let fade = frac.map(fade);
+    let mut result = 0.;
+    for corner in 0..8 {
+        let offset = [corner & 1, (corner >> 1) & 1, (corner >> 2) & 1];
+        let g = GRADIENTS[(hash(
+            cell[0] as i64 + offset[0],
+            cell[1] as i64 + offset[1],
+            cell[2] as i64 + offset[2],
+        ) % 12) as usize];
+        let mut weight = 1.;
+        let mut dot = 0.;
+        for axis in 0..3 {
+            let d = frac[axis] - offset[axis] as f64;
+            dot += g[axis] * d;
+            weight *= if offset[axis] == 1 {
+                fade[axis]
+            } else {
+                1. - fade[axis]
+            };
+        }
+        result += weight * dot;
+    }
+    result
+}
+
+/// Numerical gradient of `perlin` at `p`.
+pub fn perlin_gradient(p: ColumnVec<3>) -> ColumnVec<3> {
+    let e = 1e-4;
+    let dx = ColumnVec([e, 0., 0.]);
+    let dy = ColumnVec([0., e, 0.]);
+    let dz = ColumnVec([0., 0., e]);
+    ColumnVec([
+        perlin(p + dx) - perlin(p - dx),
+        perlin(p + dy) - perlin(p - dy),
+        perlin(p + dz) - perlin(p - dz),
+    ]) / (2. * e)
+}
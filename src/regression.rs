@@ -0,0 +1,257 @@
+use std::sync::Arc;
+
+use elgan_math::linalg::ColumnVec;
+use image::RgbImage;
+
+use crate::{
+    camera::Camera,
+    hittable::{Sphere, World},
+    integrator::{Integrator, PathTracer, T_MIN},
+    material::{ColorType, Dielectric, Emissive, Lambertian, Material, Metal},
+    render::{render_image, RenderSettings},
+    scenes::furnace,
+    Ray,
+};
+
+/// Small fixed scenes that together cover every material, cheap enough to render at
+/// reference quality in a few seconds. Each is given as its name, world and camera.
+pub fn presets() -> Vec<(&'static str, World, Camera)> {
+    let camera = Camera::new(96, 64, 2., 1., ColumnVec([0., 0.5, 0.]));
+    let ground = || -> Box<Sphere> {
+        Box::new(Sphere {
+            center: ColumnVec([0., -100.5, -1.]),
+            radius: 100.,
+            material: Arc::new(Lambertian {
+                color: ColorType::Checker(
+                    ColumnVec([0.4, 0.8, 0.4]),
+                    ColumnVec([0.6, 1., 0.6]),
+                    0.25,
+                ),
+            }),
+        })
+    };
+
+    let mut diffuse = World::new();
+    diffuse.push(ground());
+    diffuse.push(Box::new(Sphere {
+        center: ColumnVec([0., 0., -2.]),
+        radius: 0.5,
+        material: Arc::new(Lambertian {
+            color: ColorType::Solid(ColumnVec([0.8, 0.3, 0.3])),
+        }),
+    }));
+
+    let mut specular = World::new();
+    specular.push(ground());
+    specular.push(Box::new(Sphere {
+        center: ColumnVec([-0.6, 0., -2.]),
+        radius: 0.5,
+        material: Arc::new(Metal {
+            color: ColorType::Solid(ColumnVec([0.8; 3])),
+            fuzz: 0.1,
+        }),
+    }));
+    specular.push(Box::new(Sphere {
+        center: ColumnVec([0.6, 0., -2.]),
+        radius: 0.5,
+        material: Arc::new(Dielectric {
+            color: ColorType::Solid(ColumnVec([1.; 3])),
+            ir: 1.5,
+            priority: 0,
+        }),
+    }));
+
+    let mut emissive = World::new();
+    emissive.push(ground());
+    emissive.push(Box::new(Sphere {
+        center: ColumnVec([0., 1.5, -2.]),
+        radius: 0.5,
+        material: Arc::new(Emissive {
+            color: ColorType::Solid(ColumnVec([4., 3., 2.])),
+        }),
+    }));
+    emissive.push(Box::new(Sphere {
+        center: ColumnVec([0., 0., -2.]),
+        radius: 0.5,
+        material: Arc::new(Lambertian {
+            color: ColorType::Solid(ColumnVec([0.7; 3])),
+        }),
+    }));
+
+    vec![
+        ("diffuse", diffuse, camera),
+        ("specular", specular, camera),
+        ("emissive", emissive, camera),
+    ]
+}
+
+/// Root mean square difference between two images of the same size, from 0 to 1.
+/// Returns `None` if the sizes differ.
+pub fn image_difference(a: &RgbImage, b: &RgbImage) -> Option<f64> {
+    if a.dimensions() != b.dimensions() {
+        return None;
+    }
+    let total: f64 = a
+        .as_raw()
+        .iter()
+        .zip(b.as_raw())
+        .map(|(x, y)| ((*x as f64 - *y as f64) / 255.).powi(2))
+        .sum();
+    Some((total / a.as_raw().len() as f64).sqrt())
+}
+
+/// Seed the presets are rendered with, so a render only differs from its reference when
+/// the renderer's output changes.
+pub const PRESET_SEED: u32 = 0;
+
+fn render_preset(world: &World, camera: Camera, samples_per_pixel: u32) -> RgbImage {
+    render_image(
+        world,
+        camera,
+        &RenderSettings {
+            seed: Some(PRESET_SEED),
+            ..RenderSettings::new(samples_per_pixel)
+        },
+        0..camera.width,
+        0..camera.height,
+    )
+}
+
+/// Renders every preset with `PRESET_SEED` and saves it as `{directory}/{name}.png`, to record
+/// references from a known good build. Returns false if any of them couldn't be saved.
+pub fn record_presets(directory: &str, samples_per_pixel: u32) -> bool {
+    let mut saved = true;
+    for (name, world, camera) in presets() {
+        let filename = format!("{}/{}.png", directory, name);
+        match render_preset(&world, camera, samples_per_pixel).save(&filename) {
+            Ok(()) => println!("{}: saved reference as {}", name, filename),
+            Err(_) => {
+                println!("{}: error saving reference {}", name, filename);
+                saved = false;
+            }
+        }
+    }
+    saved
+}
+
+/// Renders every preset with `PRESET_SEED` and compares it with `{directory}/{name}.png` as
+/// saved by `record_presets` with the same `samples_per_pixel`, returning true if all of them
+/// are within `tolerance` of their reference. With the seed fixed the tolerance only needs to
+/// cover rounding, so even small shading changes fail. A missing reference fails too.
+pub fn check_presets(directory: &str, samples_per_pixel: u32, tolerance: f64) -> bool {
+    let mut passed = true;
+    for (name, world, camera) in presets() {
+        let filename = format!("{}/{}.png", directory, name);
+        let reference = match image::open(&filename) {
+            Ok(reference) => reference.to_rgb8(),
+            Err(_) => {
+                println!("{}: no reference at {}", name, filename);
+                passed = false;
+                continue;
+            }
+        };
+        let img = render_preset(&world, camera, samples_per_pixel);
+        match image_difference(&img, &reference) {
+            Some(difference) if difference <= tolerance => {
+                println!("{}: ok ({:.4})", name, difference)
+            }
+            Some(difference) => {
+                println!("{}: differs from reference ({:.4})", name, difference);
+                passed = false;
+            }
+            None => {
+                println!("{}: reference has a different size", name);
+                passed = false;
+            }
+        }
+    }
+    passed
+}
+
+/// Average color seen looking at the `furnace` scene for `material` from random directions,
+/// over `samples` rays aimed at random points across the sphere.
+pub fn furnace_radiance(material: Arc<dyn Material>, samples: u32) -> ColumnVec<3> {
+    let world = furnace(material);
+    let integrator = PathTracer::default();
+    let mut total = ColumnVec::zero();
+    for _ in 0..samples {
+        let direction = ColumnVec::random_inside_sphere().normalized();
+        // Pick a point on the disk the sphere covers as seen from `direction`.
+        let mut offset = ColumnVec::random_inside_sphere();
+        offset = offset - direction * (offset * direction);
+        let origin = offset - direction * 4.;
+        total = total + integrator.li(Ray::new(origin, direction), &world, T_MIN, f64::INFINITY);
+    }
+    total / samples as f64
+}
+
+/// Panics if a white `material` reflects more or less light in the furnace than it receives,
+/// by more than `tolerance` in any channel. Materials with a color other than white absorb
+/// light on purpose and can't be checked this way.
+pub fn assert_energy_conserving(material: Arc<dyn Material>, samples: u32, tolerance: f64) {
+    let radiance = furnace_radiance(material, samples);
+    for channel in radiance.0 {
+        assert!(
+            (channel - 1.).abs() <= tolerance,
+            "furnace radiance is {:?}, expected 1 within {}",
+            radiance.0,
+            tolerance
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh empty directory for references, removed when the test is done with it.
+    fn reference_directory(name: &str) -> String {
+        let directory = std::env::temp_dir().join(format!(
+            "raytracing-regression-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&directory);
+        std::fs::create_dir_all(&directory).unwrap();
+        directory.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn seeded_renders_match_exactly() {
+        for (name, world, camera) in presets() {
+            assert_eq!(
+                image_difference(
+                    &render_preset(&world, camera, 4),
+                    &render_preset(&world, camera, 4)
+                ),
+                Some(0.),
+                "{} differs between seeded renders",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn presets_match_recorded_references() {
+        let directory = reference_directory("match");
+        assert!(record_presets(&directory, 4));
+        assert!(check_presets(&directory, 4, 0.));
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn changed_output_fails_check() {
+        let directory = reference_directory("changed");
+        assert!(record_presets(&directory, 2));
+        // more samples stand in for a change to the renderer
+        assert!(!check_presets(&directory, 4, 0.001));
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn missing_reference_fails_check() {
+        let directory = reference_directory("missing");
+        assert!(!check_presets(&directory, 4, 1.));
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -1,36 +1,26 @@
-use std::sync::{Arc, Mutex};
+use std::{
+    ops::Range,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 
 use elgan_math::linalg::ColumnVec;
-use image::{ImageBuffer, RgbImage};
+use image::{imageops, ImageBuffer, ImageFormat, Rgb, RgbImage};
 use rand::Rng;
-use rayon::prelude::{IntoParallelRefIterator, ParallelIterator};
+use rayon::prelude::{ParallelBridge, ParallelIterator};
 
 use crate::{
     camera::Camera,
+    color::{ColorSpace, Lut3d},
     hittable::{Hittable, Sphere, World},
+    integrator::{Integrator, PathTracer, T_MIN},
     material::{ColorType, Dielectric, Emissive, Lambertian, Metal},
-    to_color, Ray,
+    noise::pcg_hash,
+    sampler,
+    snapshot::Snapshot,
+    stats,
 };
 
-fn ray_color(r: Ray, world: &World, depth: i32) -> ColumnVec<3> {
-    if depth <= 0 {
-        return ColumnVec::zero();
-    }
-    if let Some(rec) = world.hit(r, 0.00069420, f64::INFINITY) {
-        match rec.material.clone().scatter(r, rec) {
-            (Some(scattered), Some(color)) => {
-                return color.component_mul(ray_color(scattered, world, depth - 1));
-            }
-            (None, Some(color)) => return color,
-            _ => (),
-        }
-    }
-    let unit_dir = r.direction.normalized();
-    let t = 0.5 * unit_dir[1] + 0.5;
-    // (1. - t) * ColumnVec([1.; 3]) + t * ColumnVec([0.5, 0.7, 1.0])
-    (1. - t) * ColumnVec([0.08, 0.1, 0.2]) + t * ColumnVec([0.032, 0.04, 0.08])
-}
-
 pub fn ray_diffuse_glass() {
     let mut world = World::new();
     world.push(Box::new(Sphere {
@@ -67,6 +57,7 @@ pub fn ray_diffuse_glass() {
         material: Arc::new(Dielectric {
             color: ColorType::Solid(ColumnVec([1.; 3])),
             ir: 1.3,
+            priority: 0,
         }),
     }));
     world.push(Box::new(Sphere {
@@ -75,6 +66,7 @@ pub fn ray_diffuse_glass() {
         material: Arc::new(Dielectric {
             color: ColorType::Solid(ColumnVec([1.; 3])),
             ir: 1.3,
+            priority: 0,
         }),
     }));
     world.push(Box::new(Sphere {
@@ -83,6 +75,7 @@ pub fn ray_diffuse_glass() {
         material: Arc::new(Dielectric {
             color: ColorType::Solid(ColumnVec([1.; 3])),
             ir: 1.3,
+            priority: 0,
         }),
     }));
     for i in 0..40 {
@@ -125,6 +118,7 @@ pub fn ray_diffuse_glass() {
                 material: Arc::new(Dielectric {
                     ir: 1.3,
                     color: ColorType::Solid(ColumnVec::random_box(0.9, 1.)),
+                    priority: 0,
                 }),
             }));
         }
@@ -143,37 +137,826 @@ pub fn ray_diffuse_glass() {
     )
 }
 
+/// The order columns are handed to render threads in.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum ColumnOrder {
+    #[default]
+    LeftToRight,
+    /// Starting at the middle of the frame and alternating outwards, so the center of a
+    /// preview resolves first.
+    CenterOut,
+    /// Every `n`th column first, then the ones between them, so a coarse version of the
+    /// whole frame is available early.
+    Interleaved(u32),
+}
+
+impl ColumnOrder {
+    pub fn order(self, columns: Range<u32>) -> Vec<u32> {
+        match self {
+            ColumnOrder::LeftToRight => columns.collect(),
+            ColumnOrder::CenterOut => {
+                let middle = columns.start + columns.len() as u32 / 2;
+                let mut order: Vec<u32> = columns.collect();
+                order.sort_by_key(|&i| (i as i64 - middle as i64).abs());
+                order
+            }
+            ColumnOrder::Interleaved(n) => {
+                let n = n.max(1);
+                (0..n)
+                    .flat_map(|offset| columns.clone().skip(offset as usize).step_by(n as usize))
+                    .collect()
+            }
+        }
+    }
+}
+
+/// What to do with samples whose radiance is NaN or infinite, which would otherwise leave
+/// black or white pixels in the image.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum NonFinitePolicy {
+    #[default]
+    Keep,
+    /// Replace NaN and infinite channels with zero, and report how many samples had them.
+    Clamp,
+    /// Panic with the pixel and sample at the first one found.
+    Abort,
+}
+
+/// Floating point precision samples are added up and linear pixels stored in.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Precision {
+    /// Halves the memory of the linear pixel buffer, for very large frames.
+    F32,
+    /// Avoids losing small contributions at very high sample counts.
+    #[default]
+    F64,
+}
+
+impl Precision {
+    /// `color` rounded to this precision.
+    pub fn round(self, color: ColumnVec<3>) -> ColumnVec<3> {
+        match self {
+            Precision::F32 => ColumnVec(color.0.map(|x| x as f32 as f64)),
+            Precision::F64 => color,
+        }
+    }
+}
+
+/// Linear pixel values by row, stored at a chosen precision.
+pub enum HdrBuffer {
+    F32(Vec<[f32; 3]>),
+    F64(Vec<ColumnVec<3>>),
+}
+
+impl HdrBuffer {
+    pub fn new(len: usize, precision: Precision) -> Self {
+        match precision {
+            Precision::F32 => HdrBuffer::F32(vec![[0.; 3]; len]),
+            Precision::F64 => HdrBuffer::F64(vec![ColumnVec::zero(); len]),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            HdrBuffer::F32(pixels) => pixels.len(),
+            HdrBuffer::F64(pixels) => pixels.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, index: usize) -> ColumnVec<3> {
+        match self {
+            HdrBuffer::F32(pixels) => ColumnVec(pixels[index].map(|x| x as f64)),
+            HdrBuffer::F64(pixels) => pixels[index],
+        }
+    }
+
+    pub fn set(&mut self, index: usize, color: ColumnVec<3>) {
+        match self {
+            HdrBuffer::F32(pixels) => pixels[index] = color.0.map(|x| x as f32),
+            HdrBuffer::F64(pixels) => pixels[index] = color,
+        }
+    }
+}
+
+/// Options controlling how an image is rendered.
+#[derive(Clone)]
+pub struct RenderSettings {
+    pub samples_per_pixel: u32,
+    /// If set, a diagnostic image showing how long each column took, with the settings
+    /// and total time written in the corner, is saved here alongside the render.
+    pub stats_filename: Option<String>,
+    /// Color space the image is written in. PNG files are not tagged with it, so it is
+    /// printed when the image is saved and included in the statistics image.
+    pub color_space: ColorSpace,
+    /// Grading applied to the final pixel values, for example loaded with `Lut3d::load`.
+    pub lut: Option<Lut3d>,
+    pub column_order: ColumnOrder,
+    /// If set, a warning is printed before rendering when `estimate_memory` exceeds this
+    /// many bytes.
+    pub memory_budget: Option<usize>,
+    /// Set `PathTracer::report_non_finite` as well to find the bounce a bad value comes from.
+    pub non_finite: NonFinitePolicy,
+    /// If set, a PNG of the full image is created here before rendering and the columns
+    /// finished since the last update are written into it in place every
+    /// `snapshot_interval`, so a recent snapshot is always on disk without re-encoding the
+    /// image.
+    pub snapshot_filename: Option<String>,
+    pub snapshot_interval: Duration,
+    /// Extra exposures, in stops relative to the normal one, to save from the same linear
+    /// pixel values. Each is saved next to the image with the stops added to its name, as in
+    /// `render_+2ev.png`.
+    pub exposure_brackets: Vec<f64>,
+    /// If set, an image of how long each pixel took, to find the objects or materials that
+    /// dominate render time, is saved here alongside the render.
+    pub pixel_times_filename: Option<String>,
+    pub precision: Precision,
+    /// If set, a false color map of exposure is saved here and a histogram of luminance is
+    /// printed, to check lighting levels before choosing tone mapping.
+    pub false_color_filename: Option<String>,
+    /// If set, a downscaled JPEG of the image so far is written here every
+    /// `preview_interval` and when the render finishes, small enough to check on remotely.
+    pub preview_filename: Option<String>,
+    pub preview_interval: Duration,
+    /// Width of the preview in pixels; the height keeps the image's aspect ratio.
+    pub preview_width: u32,
+    /// Frame number in an animation, so sample positions differ between frames.
+    pub frame: u32,
+    /// If set, every random choice made while tracing comes from a sequence seeded by this,
+    /// the pixel, the frame and the sample, so the same settings render the same image.
+    pub seed: Option<u32>,
+}
+
+impl RenderSettings {
+    pub fn new(samples_per_pixel: u32) -> Self {
+        Self {
+            samples_per_pixel,
+            stats_filename: None,
+            color_space: ColorSpace::default(),
+            lut: None,
+            column_order: ColumnOrder::default(),
+            memory_budget: None,
+            non_finite: NonFinitePolicy::default(),
+            snapshot_filename: None,
+            snapshot_interval: Duration::from_secs(10),
+            exposure_brackets: vec![],
+            pixel_times_filename: None,
+            precision: Precision::default(),
+            false_color_filename: None,
+            preview_filename: None,
+            preview_interval: Duration::from_secs(60),
+            preview_width: 480,
+            frame: 0,
+            seed: None,
+        }
+    }
+
+    /// Whether the unquantized colors are kept, for exposure brackets or false color.
+    fn needs_hdr(&self) -> bool {
+        !self.exposure_brackets.is_empty() || self.false_color_filename.is_some()
+    }
+
+    /// Quantizes a linear color for the pixel at `x`, `y` in the output color space and
+    /// applies the LUT, if any.
+    fn encode(&self, color: ColumnVec<3>, x: u32, y: u32) -> Rgb<u8> {
+        let pixel = self.color_space.to_color_dithered(color, x, y);
+        match &self.lut {
+            Some(lut) => lut.apply_to_pixel(pixel),
+            None => pixel,
+        }
+    }
+}
+
+/// Position within the pixel at `x`, `y` of sample number `sample`. Samples follow the R2
+/// low-discrepancy sequence, shifted by a hash of the pixel and frame so that neighboring
+/// pixels don't share the same pattern.
+fn pixel_jitter(x: u32, y: u32, frame: u32, sample: u32) -> (f64, f64) {
+    const G: f64 = 1.324_717_957_244_746;
+    let seed = pcg_hash(x ^ pcg_hash(y ^ pcg_hash(frame)));
+    let shift_u = pcg_hash(seed) as f64 / 4294967296.;
+    let shift_v = pcg_hash(seed ^ 0x9e3779b9) as f64 / 4294967296.;
+    let n = sample as f64 + 1.;
+    ((shift_u + n / G).fract(), (shift_v + n / (G * G)).fract())
+}
+
+/// `filename` with `_{stops}ev` inserted before its extension.
+fn bracket_filename(filename: &str, stops: f64) -> String {
+    match filename.rfind('.') {
+        Some(dot) => format!("{}_{:+}ev{}", &filename[..dot], stops, &filename[dot..]),
+        None => format!("{}_{:+}ev", filename, stops),
+    }
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self::new(100)
+    }
+}
+
 pub fn render(world: World, camera: Camera, samples_per_pixel: u32, filename: &str) {
-    let img: Arc<Mutex<RgbImage>> =
-        Arc::new(Mutex::new(ImageBuffer::new(camera.width, camera.height)));
-    let count = Arc::new(Mutex::new(0));
+    render_region(
+        world,
+        camera,
+        samples_per_pixel,
+        0..camera.width,
+        0..camera.height,
+        filename,
+    )
+}
 
-    rayon::ThreadPoolBuilder::new()
+/// Like `render`, but with full control over the settings and computing the light along
+/// each camera ray with `integrator`.
+pub fn render_with(
+    world: World,
+    camera: Camera,
+    settings: &RenderSettings,
+    integrator: &dyn Integrator,
+    filename: &str,
+) {
+    let progress = Progress::new(camera.width, camera.height);
+    render_progress(world, camera, settings, integrator, filename, progress);
+}
+
+/// Like `render_with`, but calls `on_column` with the x coordinate and pixels, from top to
+/// bottom, of each column as it finishes, so other programs can show the render in progress.
+/// Columns finish in parallel, so calls can come from several threads at once.
+pub fn render_with_callback<F>(
+    world: World,
+    camera: Camera,
+    settings: &RenderSettings,
+    integrator: &dyn Integrator,
+    filename: &str,
+    on_column: F,
+) where
+    F: Fn(u32, &[Rgb<u8>]) + Send + Sync + 'static,
+{
+    let progress = Progress::new(camera.width, camera.height).with_column_callback(on_column);
+    render_progress(world, camera, settings, integrator, filename, progress);
+}
+
+fn render_progress(
+    world: World,
+    camera: Camera,
+    settings: &RenderSettings,
+    integrator: &dyn Integrator,
+    filename: &str,
+    progress: Progress,
+) {
+    build_thread_pool();
+    let progress = if settings.needs_hdr() {
+        progress.with_hdr(settings.precision)
+    } else {
+        progress
+    };
+    let progress = match settings.pixel_times_filename {
+        Some(_) => progress.with_pixel_times(),
+        None => progress,
+    };
+    let memory = estimate_memory(&world, &camera, settings);
+    if let Some(budget) = settings.memory_budget.filter(|&budget| memory > budget) {
+        println!(
+            "warning: render needs about {} MB, over the budget of {} MB",
+            memory >> 20,
+            budget >> 20
+        );
+    }
+    render_into(
+        &progress,
+        &world,
+        camera,
+        settings,
+        integrator,
+        0..camera.width,
+        0..camera.height,
+    );
+    save(&progress.image.lock().unwrap(), filename);
+    if let Some(hdr) = &progress.hdr {
+        let hdr = hdr.lock().unwrap();
+        for &stops in &settings.exposure_brackets {
+            let scale = 2f64.powf(stops);
+            let img = ImageBuffer::from_fn(camera.width, camera.height, |x, y| {
+                settings.encode(hdr.get((y * camera.width + x) as usize) * scale, x, y)
+            });
+            save(&img, &bracket_filename(filename, stops));
+        }
+        if let Some(false_color_filename) = &settings.false_color_filename {
+            save(
+                &stats::false_color(&hdr, camera.width, camera.height),
+                false_color_filename,
+            );
+            let histogram = stats::luminance_histogram(&hdr, -8, 8);
+            for (stops, count) in (-8..8).zip(histogram) {
+                println!(
+                    "{:+3} ev {:5.1}%",
+                    stops,
+                    100. * count as f64 / hdr.len() as f64
+                );
+            }
+        }
+    }
+    if settings.color_space != ColorSpace::Rec709 {
+        println!("{} is in {}", filename, settings.color_space.name());
+    }
+    if let Some(stats_filename) = &settings.stats_filename {
+        let lines = [
+            format!(
+                "{}x{} {} spp",
+                camera.width, camera.height, settings.samples_per_pixel
+            ),
+            format!("{:.1} s", progress.started.elapsed().as_secs_f64()),
+            settings.color_space.name().to_string(),
+        ];
+        save(&stats::stats_image(&progress, &lines), stats_filename);
+    }
+    if let (Some(pixel_times_filename), Some(img)) = (
+        &settings.pixel_times_filename,
+        stats::pixel_time_image(&progress),
+    ) {
+        save(&img, pixel_times_filename);
+    }
+}
+
+/// Renders only the pixels in `columns` x `rows` of the full frame into an image of that size,
+/// so one frame can be split between independent jobs and reassembled with `merge_regions`.
+pub fn render_region(
+    world: World,
+    camera: Camera,
+    samples_per_pixel: u32,
+    columns: Range<u32>,
+    rows: Range<u32>,
+    filename: &str,
+) {
+    build_thread_pool();
+    save(
+        &render_image(
+            &world,
+            camera,
+            &RenderSettings::new(samples_per_pixel),
+            columns,
+            rows,
+        ),
+        filename,
+    );
+}
+
+/// Renders the same world from several cameras in one call, sharing the scene between them.
+/// Each view is a camera and the filename its image is saved to.
+pub fn render_cameras(world: World, views: &[(Camera, &str)], samples_per_pixel: u32) {
+    build_thread_pool();
+    for (camera, filename) in views {
+        save(
+            &render_image(
+                &world,
+                *camera,
+                &RenderSettings::new(samples_per_pixel),
+                0..camera.width,
+                0..camera.height,
+            ),
+            filename,
+        );
+    }
+}
+
+/// Renders each render layer of `world` separately, with objects on other layers held out.
+/// Each layer is given as its number and the filename its image is saved to.
+pub fn render_layers(
+    world: &mut World,
+    camera: Camera,
+    samples_per_pixel: u32,
+    layers: &[(u32, &str)],
+) {
+    build_thread_pool();
+    for (layer, filename) in layers {
+        world.active_layer = Some(*layer);
+        save(
+            &render_image(
+                world,
+                camera,
+                &RenderSettings::new(samples_per_pixel),
+                0..camera.width,
+                0..camera.height,
+            ),
+            filename,
+        );
+    }
+    world.active_layer = None;
+}
+
+/// Renders `frames` frames, building each frame's scene with `scene(frame, time)` where time
+/// is in seconds. Frames are saved as `{name}_0000.png`, `{name}_0001.png`, ...
+pub fn render_animation_with<F>(
+    frames: u32,
+    frames_per_second: f64,
+    samples_per_pixel: u32,
+    name: &str,
+    mut scene: F,
+) where
+    F: FnMut(u32, f64) -> (World, Camera),
+{
+    build_thread_pool();
+    for frame in 0..frames {
+        let (world, camera) = scene(frame, frame as f64 / frames_per_second);
+        save(
+            &render_image(
+                &world,
+                camera,
+                &RenderSettings {
+                    frame,
+                    ..RenderSettings::new(samples_per_pixel)
+                },
+                0..camera.width,
+                0..camera.height,
+            ),
+            &format!("{}_{:04}.png", name, frame),
+        );
+    }
+}
+
+/// Approximate bytes needed to render `world` with `camera` and `settings`: the scene's
+/// geometry, the image with its progress tracking, and the unquantized colors, pixel times
+/// and snapshot file if `settings` keeps them. Materials are not counted.
+pub fn estimate_memory(world: &World, camera: &Camera, settings: &RenderSettings) -> usize {
+    let pixels = camera.width as usize * camera.height as usize;
+    let hdr = match (settings.needs_hdr(), settings.precision) {
+        (false, _) => 0,
+        (true, Precision::F32) => std::mem::size_of::<[f32; 3]>(),
+        (true, Precision::F64) => std::mem::size_of::<ColumnVec<3>>(),
+    };
+    let pixel_time = match settings.pixel_times_filename {
+        Some(_) => std::mem::size_of::<Duration>(),
+        None => 0,
+    };
+    // the snapshot keeps a copy of its file, which is about the size of the image
+    let snapshot = match settings.snapshot_filename {
+        Some(_) => 3,
+        None => 0,
+    };
+    world.memory_size()
+        + pixels * (3 + hdr + pixel_time + snapshot)
+        + camera.width as usize * std::mem::size_of::<Option<Duration>>()
+}
+
+fn build_thread_pool() {
+    // Fails if an earlier render in this process already built it, which is fine.
+    let _ = rayon::ThreadPoolBuilder::new()
         .num_threads(6)
-        .build_global()
-        .unwrap();
+        .build_global();
+}
+
+/// One render for a `RenderQueue`.
+pub struct RenderJob {
+    pub world: World,
+    pub camera: Camera,
+    pub settings: RenderSettings,
+    pub integrator: Box<dyn Integrator>,
+    pub filename: String,
+}
+
+impl RenderJob {
+    /// A path traced job with default settings apart from the sample count.
+    pub fn new(world: World, camera: Camera, samples_per_pixel: u32, filename: &str) -> Self {
+        Self {
+            world,
+            camera,
+            settings: RenderSettings::new(samples_per_pixel),
+            integrator: Box::new(PathTracer::default()),
+            filename: filename.to_string(),
+        }
+    }
+}
+
+pub struct JobResult {
+    pub filename: String,
+    pub elapsed: Duration,
+}
 
-    (0..camera.width)
-        .collect::<Vec<_>>()
-        .par_iter()
+/// Renders jobs one after another on the same thread pool, for batches left to run
+/// unattended. Materials are shared between jobs' worlds by cloning their `Arc`s.
+#[derive(Default)]
+pub struct RenderQueue {
+    pub jobs: Vec<RenderJob>,
+}
+
+impl RenderQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, job: RenderJob) {
+        self.jobs.push(job)
+    }
+
+    /// Runs every job in order, returning how long each took.
+    pub fn run(self) -> Vec<JobResult> {
+        let count = self.jobs.len();
+        self.jobs
+            .into_iter()
+            .enumerate()
+            .map(|(index, job)| {
+                println!("job {} of {}: {}", index + 1, count, job.filename);
+                let started = Instant::now();
+                render_with(
+                    job.world,
+                    job.camera,
+                    &job.settings,
+                    job.integrator.as_ref(),
+                    &job.filename,
+                );
+                JobResult {
+                    filename: job.filename,
+                    elapsed: started.elapsed(),
+                }
+            })
+            .collect()
+    }
+}
+
+/// State of a render in progress, shared with anything watching it.
+pub struct Progress {
+    pub image: Mutex<RgbImage>,
+    /// How long each finished column took to render.
+    pub column_times: Mutex<Vec<Option<Duration>>>,
+    pub started: Instant,
+    /// Linear pixel values, kept if requested with `with_hdr`.
+    pub hdr: Option<Mutex<HdrBuffer>>,
+    /// Time spent on each pixel by row, kept if requested with `with_pixel_times`.
+    pub pixel_times: Option<Mutex<Vec<Duration>>>,
+    on_column: Option<ColumnCallback>,
+}
+
+type ColumnCallback = Box<dyn Fn(u32, &[Rgb<u8>]) + Send + Sync>;
+
+impl Progress {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            image: Mutex::new(ImageBuffer::new(width, height)),
+            column_times: Mutex::new(vec![None; width as usize]),
+            started: Instant::now(),
+            hdr: None,
+            pixel_times: None,
+            on_column: None,
+        }
+    }
+
+    /// Also keeps the linear value of every pixel before it is quantized.
+    pub fn with_hdr(self, precision: Precision) -> Self {
+        let (width, height) = self.image.lock().unwrap().dimensions();
+        Self {
+            hdr: Some(Mutex::new(HdrBuffer::new(
+                width as usize * height as usize,
+                precision,
+            ))),
+            ..self
+        }
+    }
+
+    /// Also records how long every pixel took.
+    pub fn with_pixel_times(self) -> Self {
+        let (width, height) = self.image.lock().unwrap().dimensions();
+        Self {
+            pixel_times: Some(Mutex::new(vec![
+                Duration::ZERO;
+                width as usize * height as usize
+            ])),
+            ..self
+        }
+    }
+
+    /// Calls `on_column` with the x coordinate and pixels of each column as it finishes.
+    pub fn with_column_callback<F>(self, on_column: F) -> Self
+    where
+        F: Fn(u32, &[Rgb<u8>]) + Send + Sync + 'static,
+    {
+        Self {
+            on_column: Some(Box::new(on_column)),
+            ..self
+        }
+    }
+
+    pub fn fraction_done(&self) -> f64 {
+        let column_times = self.column_times.lock().unwrap();
+        column_times.iter().filter(|time| time.is_some()).count() as f64 / column_times.len() as f64
+    }
+
+    /// Estimated time until the render finishes, assuming columns take similar time.
+    pub fn remaining(&self) -> Option<Duration> {
+        let fraction = self.fraction_done();
+        if fraction == 0. {
+            return None;
+        }
+        Some(self.started.elapsed().mul_f64((1. - fraction) / fraction))
+    }
+}
+
+/// Like `render`, but also serves a page showing the image in progress at `address`,
+/// for example "0.0.0.0:8080".
+#[cfg(feature = "dashboard")]
+pub fn render_with_dashboard(
+    world: World,
+    camera: Camera,
+    samples_per_pixel: u32,
+    filename: &str,
+    address: &str,
+) {
+    build_thread_pool();
+    let progress = Arc::new(Progress::new(camera.width, camera.height));
+    match crate::dashboard::serve(address, progress.clone()) {
+        Ok(()) => println!("serving progress on http://{}", address),
+        Err(_) => println!("error starting dashboard on {}", address),
+    }
+    render_into(
+        &progress,
+        &world,
+        camera,
+        &RenderSettings::new(samples_per_pixel),
+        &PathTracer::default(),
+        0..camera.width,
+        0..camera.height,
+    );
+    save(&progress.image.lock().unwrap(), filename);
+}
+
+pub(crate) fn render_image(
+    world: &World,
+    camera: Camera,
+    settings: &RenderSettings,
+    columns: Range<u32>,
+    rows: Range<u32>,
+) -> RgbImage {
+    let progress = Progress::new(columns.len() as u32, rows.len() as u32);
+    render_into(
+        &progress,
+        world,
+        camera,
+        settings,
+        &PathTracer::default(),
+        columns,
+        rows,
+    );
+    progress.image.into_inner().unwrap()
+}
+
+fn save_preview(img: &RgbImage, width: u32, filename: &str) {
+    let width = width.min(img.width()).max(1);
+    let height = (img.height() as u64 * width as u64 / img.width() as u64).max(1) as u32;
+    let preview = imageops::resize(img, width, height, imageops::FilterType::Triangle);
+    if preview
+        .save_with_format(filename, ImageFormat::Jpeg)
+        .is_err()
+    {
+        println!("error saving preview {}", filename);
+    }
+}
+
+fn render_into(
+    progress: &Progress,
+    world: &World,
+    camera: Camera,
+    settings: &RenderSettings,
+    integrator: &dyn Integrator,
+    columns: Range<u32>,
+    rows: Range<u32>,
+) {
+    let count = Arc::new(Mutex::new(0));
+    let non_finite = Mutex::new(0);
+    let samples_per_pixel = settings.samples_per_pixel;
+    let last_preview = Mutex::new(Instant::now());
+    let snapshot =
+        settings.snapshot_filename.as_ref().and_then(|filename| {
+            match Snapshot::create(filename, columns.len() as u32, rows.len() as u32) {
+                Ok(snapshot) => Some(Mutex::new((snapshot, Instant::now()))),
+                Err(_) => {
+                    println!("error creating snapshot {}", filename);
+                    None
+                }
+            }
+        });
+
+    // Bridging hands columns out one at a time in order, where splitting the range would
+    // start each thread at a different part of the frame.
+    settings
+        .column_order
+        .order(columns.clone())
+        .into_iter()
+        .par_bridge()
         .for_each(|i| {
-            for j in 0..camera.height {
+            let started = Instant::now();
+            for j in rows.clone() {
+                let pixel_started = Instant::now();
                 let mut color = ColumnVec([0.; 3]);
-                for _ in 0..samples_per_pixel {
-                    let u = (*i as f64 + rand::random::<f64>()) / (camera.width - 1) as f64;
-                    let v = ((camera.height - j) as f64 + rand::random::<f64>())
-                        / (camera.height - 1) as f64;
-                    let ray = camera.get_ray(u, v);
-                    color = color + ray_color(ray, &world, 16);
+                for sample in 0..samples_per_pixel {
+                    sampler::seed(settings.seed.map(|seed| {
+                        let pixel = pcg_hash(i ^ pcg_hash(j ^ pcg_hash(settings.frame)));
+                        pcg_hash(seed ^ pcg_hash(sample ^ pixel))
+                    }));
+                    let (du, dv) = pixel_jitter(i, j, settings.frame, sample);
+                    let u = (i as f64 + du) / (camera.width - 1) as f64;
+                    let v = ((camera.height - j) as f64 + dv) / (camera.height - 1) as f64;
+                    let (ray, weight) = camera.sample_ray(u, v);
+                    let (near, far) = camera.clip_range(ray);
+                    let mut li = integrator
+                        .li(ray, world, near.max(T_MIN), far)
+                        .component_mul(weight);
+                    if settings.non_finite != NonFinitePolicy::Keep
+                        && li.0.iter().any(|x| !x.is_finite())
+                    {
+                        if settings.non_finite == NonFinitePolicy::Abort {
+                            panic!(
+                                "non-finite radiance {:?} at pixel ({}, {}) sample {}",
+                                li.0, i, j, sample
+                            );
+                        }
+                        li = ColumnVec(li.0.map(|x| if x.is_finite() { x } else { 0. }));
+                        *non_finite.lock().unwrap() += 1;
+                    }
+                    color = settings.precision.round(color + li);
+                }
+                let color = color / samples_per_pixel as f64;
+                let index = ((j - rows.start) * columns.len() as u32 + i - columns.start) as usize;
+                if let Some(pixel_times) = &progress.pixel_times {
+                    pixel_times.lock().unwrap()[index] = pixel_started.elapsed();
+                }
+                if let Some(hdr) = &progress.hdr {
+                    hdr.lock().unwrap().set(index, color);
+                }
+                let pixel = settings.encode(color, i, j);
+                let mut image = progress.image.lock().unwrap();
+                image.put_pixel(i - columns.start, j - rows.start, pixel);
+            }
+            sampler::seed(None);
+            progress.column_times.lock().unwrap()[(i - columns.start) as usize] =
+                Some(started.elapsed());
+            if snapshot.is_some() || progress.on_column.is_some() {
+                let column: Vec<_> = {
+                    let image = progress.image.lock().unwrap();
+                    (0..rows.len() as u32)
+                        .map(|y| *image.get_pixel(i - columns.start, y))
+                        .collect()
+                };
+                if let Some(on_column) = &progress.on_column {
+                    on_column(i - columns.start, &column);
+                }
+                if let Some(snapshot) = &snapshot {
+                    let (snapshot, last_flush) = &mut *snapshot.lock().unwrap();
+                    snapshot.write_column(i - columns.start, &column);
+                    if last_flush.elapsed() >= settings.snapshot_interval {
+                        *last_flush = Instant::now();
+                        if snapshot.flush().is_err() {
+                            println!("error writing snapshot");
+                        }
+                    }
+                }
+            }
+            // Another thread holding the lock is already writing a preview.
+            if let (Some(preview_filename), Ok(mut last_preview)) =
+                (&settings.preview_filename, last_preview.try_lock())
+            {
+                if last_preview.elapsed() >= settings.preview_interval {
+                    *last_preview = Instant::now();
+                    let image = progress.image.lock().unwrap().clone();
+                    save_preview(&image, settings.preview_width, preview_filename);
                 }
-                let mut image = img.lock().unwrap();
-                image.put_pixel(*i, j, to_color(color / samples_per_pixel as f64));
             }
             *count.lock().unwrap() += 1;
             println!("{}", count.lock().unwrap());
         });
+    if let Some(preview_filename) = &settings.preview_filename {
+        save_preview(
+            &progress.image.lock().unwrap(),
+            settings.preview_width,
+            preview_filename,
+        );
+    }
+    if let Some(snapshot) = snapshot {
+        if snapshot.into_inner().unwrap().0.flush().is_err() {
+            println!("error writing snapshot");
+        }
+    }
+    let non_finite = non_finite.into_inner().unwrap();
+    if non_finite > 0 {
+        println!("clamped {} non-finite samples", non_finite);
+    }
+    integrator.finish_render();
+}
+
+/// Assembles images written by `render_region` into one frame.
+/// Each part is given as the pixel coordinates of its top left corner and its filename.
+pub fn merge_regions(width: u32, height: u32, parts: &[(u32, u32, &str)], filename: &str) {
+    let mut img: RgbImage = ImageBuffer::new(width, height);
+    for (x, y, part) in parts {
+        match image::open(part) {
+            Ok(part) => imageops::replace(&mut img, &part.to_rgb8(), *x as i64, *y as i64),
+            Err(_) => println!("error loading {}", part),
+        }
+    }
+    save(&img, filename);
+}
 
-    if img.lock().unwrap().save(filename).is_ok() {
+fn save(img: &RgbImage, filename: &str) {
+    if img.save(filename).is_ok() {
         println!("saved image as {}", filename)
     } else {
         println!("error saving image")
@@ -0,0 +1,39 @@
+use std::cell::Cell;
+
+use elgan_math::linalg::ColumnVec;
+
+use crate::noise::pcg_hash;
+
+thread_local! {
+    static STATE: Cell<Option<u32>> = const { Cell::new(None) };
+}
+
+/// Makes the random numbers drawn on this thread while rendering a fixed sequence starting
+/// from `seed`, or random again if `None`. Renders reseed for every sample, so the result
+/// doesn't depend on which thread traced it.
+pub fn seed(seed: Option<u32>) {
+    STATE.with(|state| state.set(seed));
+}
+
+/// Uniform in [0, 1), from the seeded sequence if there is one.
+pub fn random() -> f64 {
+    STATE.with(|state| match state.get() {
+        Some(current) => {
+            state.set(Some(
+                current.wrapping_mul(747796405).wrapping_add(2891336453),
+            ));
+            pcg_hash(current) as f64 / 4294967296.
+        }
+        None => rand::random(),
+    })
+}
+
+/// Uniform inside the unit sphere, from the seeded sequence if there is one.
+pub fn random_inside_sphere() -> ColumnVec<3> {
+    loop {
+        let point = ColumnVec([random(), random(), random()].map(|x| 2. * x - 1.));
+        if point * point < 1. {
+            return point;
+        }
+    }
+}
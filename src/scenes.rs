@@ -0,0 +1,354 @@
+use std::sync::Arc;
+
+use elgan_math::linalg::ColumnVec;
+use rand::Rng;
+use rayon::prelude::{IntoParallelIterator, ParallelIterator};
+
+use crate::{
+    background::{Background, Backgrounds},
+    camera::Camera,
+    hittable::{Hittable, Parallelogram, Sphere, Triangle, World},
+    material::{ColorType, Emissive, Lambertian, Material, Metal},
+    noise::perlin,
+    render::render,
+};
+
+/// The six faces of the axis aligned box between the corners `min` and `max`.
+pub fn cuboid(
+    min: ColumnVec<3>,
+    max: ColumnVec<3>,
+    material: Arc<dyn Material>,
+) -> [Parallelogram; 6] {
+    let [x0, y0, z0] = min.0;
+    let [x1, y1, z1] = max.0;
+    let face = |a: [f64; 3], b: [f64; 3], c: [f64; 3]| {
+        Parallelogram::new([ColumnVec(a), ColumnVec(b), ColumnVec(c)], material.clone())
+    };
+    [
+        face([x0, y0, z0], [x1, y0, z0], [x0, y1, z0]),
+        face([x0, y0, z1], [x1, y0, z1], [x0, y1, z1]),
+        face([x0, y0, z0], [x0, y1, z0], [x0, y0, z1]),
+        face([x1, y0, z0], [x1, y1, z0], [x1, y0, z1]),
+        face([x0, y0, z0], [x1, y0, z0], [x0, y0, z1]),
+        face([x0, y1, z0], [x1, y1, z0], [x0, y1, z1]),
+    ]
+}
+
+fn push_cuboid(
+    world: &mut World,
+    min: ColumnVec<3>,
+    max: ColumnVec<3>,
+    material: Arc<dyn Material>,
+) {
+    for face in cuboid(min, max, material) {
+        world.push(Box::new(face));
+    }
+}
+
+fn ground(size: f64, material: Arc<dyn Material>) -> Box<Parallelogram> {
+    Box::new(Parallelogram::new(
+        [
+            ColumnVec([-size, 0., size]),
+            ColumnVec([size, 0., size]),
+            ColumnVec([-size, 0., -size]),
+        ],
+        material,
+    ))
+}
+
+/// A `blocks` x `blocks` grid of buildings with random heights up to `max_height`, seen from
+/// above one edge of the city.
+pub fn city(blocks: u32, max_height: f64) -> (World, Camera) {
+    let mut world = World::new();
+    world.push(ground(
+        blocks as f64 * 4.,
+        Arc::new(Lambertian {
+            color: ColorType::Solid(ColumnVec([0.3; 3])),
+        }),
+    ));
+    let materials: [Arc<dyn Material>; 3] = [
+        Arc::new(Lambertian {
+            color: ColorType::Solid(ColumnVec([0.7, 0.65, 0.6])),
+        }),
+        Arc::new(Lambertian {
+            color: ColorType::Solid(ColumnVec([0.5, 0.55, 0.6])),
+        }),
+        Arc::new(Metal::with_roughness(
+            ColorType::Solid(ColumnVec([0.6, 0.7, 0.8])),
+            0.1,
+        )),
+    ];
+    let half = blocks as f64 / 2.;
+    for i in 0..blocks {
+        for j in 0..blocks {
+            let x = i as f64 - half;
+            let z = -(j as f64) - 2.;
+            let height = rand::thread_rng().gen_range(0.1..1.) * max_height;
+            push_cuboid(
+                &mut world,
+                ColumnVec([x + 0.15, 0., z - 0.85]),
+                ColumnVec([x + 0.85, height, z - 0.15]),
+                materials[rand::thread_rng().gen_range(0..materials.len())].clone(),
+            );
+        }
+    }
+    let camera = Camera::new(
+        640,
+        360,
+        2.,
+        1.,
+        ColumnVec([0., max_height * 1.5, blocks as f64 * 0.25]),
+    );
+    (world, camera)
+}
+
+/// Rolling hills of `resolution` x `resolution` grid cells made of triangles, `size` across and
+/// up to about `height` tall, with `trees` trees scattered over them.
+pub fn terrain(resolution: u32, size: f64, height: f64, trees: u32) -> (World, Camera) {
+    let offset = ColumnVec([0., 0., -size / 2. - 1.]);
+    let elevation = |x: f64, z: f64| {
+        let p = ColumnVec([x / size * 4., 0.5, z / size * 4.]);
+        height * (perlin(p) + 0.5 * perlin(p * 2.) + 0.5)
+    };
+    let point = |x: f64, z: f64| ColumnVec([x, elevation(x, z), z]) + offset;
+
+    let mut world = World::new();
+    let grass: Arc<dyn Material> = Arc::new(Lambertian {
+        color: ColorType::Solid(ColumnVec([0.35, 0.55, 0.25])),
+    });
+    let cell = size / resolution as f64;
+    world.par_extend(
+        (0..resolution * resolution)
+            .into_par_iter()
+            .flat_map_iter(|index| {
+                let x = (index / resolution) as f64 * cell - size / 2.;
+                let z = (index % resolution) as f64 * cell - size / 2.;
+                let corners = [
+                    point(x, z),
+                    point(x + cell, z),
+                    point(x, z + cell),
+                    point(x + cell, z + cell),
+                ];
+                let triangles: [Box<dyn Hittable>; 2] = [
+                    Box::new(Triangle::new(
+                        [corners[0], corners[1], corners[2]],
+                        grass.clone(),
+                    )),
+                    Box::new(Triangle::new(
+                        [corners[1], corners[3], corners[2]],
+                        grass.clone(),
+                    )),
+                ];
+                triangles
+            }),
+    );
+
+    let bark: Arc<dyn Material> = Arc::new(Lambertian {
+        color: ColorType::Solid(ColumnVec([0.35, 0.25, 0.15])),
+    });
+    let leaves: Arc<dyn Material> = Arc::new(Lambertian {
+        color: ColorType::Solid(ColumnVec([0.15, 0.4, 0.15])),
+    });
+    for _ in 0..trees {
+        let x = rand::thread_rng().gen_range(-size / 2.0..size / 2.);
+        let z = rand::thread_rng().gen_range(-size / 2.0..size / 2.);
+        let tree_height = rand::thread_rng().gen_range(0.5..1.) * size / 20.;
+        let base = point(x, z);
+        push_cuboid(
+            &mut world,
+            base + ColumnVec([-0.05, -0.1, -0.05]) * tree_height,
+            base + ColumnVec([0.05, 0.7, 0.05]) * tree_height,
+            bark.clone(),
+        );
+        world.push(Box::new(Sphere {
+            center: base + ColumnVec([0., 0.8, 0.]) * tree_height,
+            radius: 0.35 * tree_height,
+            material: leaves.clone(),
+        }));
+    }
+
+    let camera = Camera::new(640, 360, 2., 1., ColumnVec([0., height * 3., 1.]));
+    (world, camera)
+}
+
+fn push_sphereflake(
+    world: &mut World,
+    center: ColumnVec<3>,
+    radius: f64,
+    axis: ColumnVec<3>,
+    depth: u32,
+    material: &Arc<dyn Material>,
+) {
+    world.push(Box::new(Sphere {
+        center,
+        radius,
+        material: material.clone(),
+    }));
+    if depth == 0 {
+        return;
+    }
+    let helper = if axis[0].abs() < 0.9 {
+        ColumnVec([1., 0., 0.])
+    } else {
+        ColumnVec([0., 1., 0.])
+    };
+    let u = axis.cross(helper).normalized();
+    let v = axis.cross(u);
+    // Six children around the equator and three more towards the axis.
+    let children = (0..6)
+        .map(|k| (k as f64 * std::f64::consts::FRAC_PI_3, 0.))
+        .chain((0..3).map(|k| {
+            (
+                (k as f64 * 2. + 0.5) * std::f64::consts::FRAC_PI_3,
+                std::f64::consts::FRAC_PI_3,
+            )
+        }));
+    for (longitude, latitude) in children {
+        let direction =
+            (u * longitude.cos() + v * longitude.sin()) * latitude.cos() + axis * latitude.sin();
+        push_sphereflake(
+            world,
+            center + direction * radius * (4. / 3.),
+            radius / 3.,
+            direction,
+            depth - 1,
+            material,
+        );
+    }
+}
+
+/// Haines' sphereflake: a sphere with nine spheres a third its size on its surface, each of
+/// those with nine more, and so on `depth` times. There are about 9^depth spheres in total.
+pub fn sphereflake(depth: u32) -> (World, Camera) {
+    let mut world = World::new();
+    world.push(ground(
+        100.,
+        Arc::new(Lambertian {
+            color: ColorType::Checker(ColumnVec([0.4; 3]), ColumnVec([0.6; 3]), 0.5),
+        }),
+    ));
+    let material: Arc<dyn Material> = Arc::new(Metal::with_roughness(
+        ColorType::Solid(ColumnVec([0.8, 0.75, 0.7])),
+        0.05,
+    ));
+    push_sphereflake(
+        &mut world,
+        ColumnVec([0., 1., -4.]),
+        1.,
+        ColumnVec([0., 1., 0.]),
+        depth,
+        &material,
+    );
+    let camera = Camera::new(640, 480, 2., 1., ColumnVec([0., 1.5, 0.]));
+    (world, camera)
+}
+
+fn push_menger(
+    world: &mut World,
+    min: ColumnVec<3>,
+    size: f64,
+    depth: u32,
+    material: &Arc<dyn Material>,
+) {
+    if depth == 0 {
+        push_cuboid(world, min, min + ColumnVec([size; 3]), material.clone());
+        return;
+    }
+    let size = size / 3.;
+    for i in 0..3 {
+        for j in 0..3 {
+            for k in 0..3 {
+                // Drop the center cube and the center of each face.
+                if [i, j, k].iter().filter(|&&index| index == 1).count() > 1 {
+                    continue;
+                }
+                let corner = min + ColumnVec([i as f64, j as f64, k as f64]) * size;
+                push_menger(world, corner, size, depth - 1, material);
+            }
+        }
+    }
+}
+
+/// A Menger sponge of boxes subdivided `depth` times, made of 20^depth boxes.
+pub fn menger_sponge(depth: u32) -> (World, Camera) {
+    let mut world = World::new();
+    world.push(ground(
+        100.,
+        Arc::new(Lambertian {
+            color: ColorType::Solid(ColumnVec([0.5; 3])),
+        }),
+    ));
+    let material: Arc<dyn Material> = Arc::new(Lambertian {
+        color: ColorType::Solid(ColumnVec([0.8, 0.5, 0.3])),
+    });
+    push_menger(&mut world, ColumnVec([-1., 0., -5.]), 2., depth, &material);
+    let camera = Camera::new(640, 480, 2., 1., ColumnVec([0.7, 2.5, 0.]));
+    (world, camera)
+}
+
+/// A ball made of `material` on a checkered floor in front of a backdrop, lit by a large warm
+/// key light and a dimmer cool fill, for previewing materials under the same conditions.
+pub fn material_ball(material: Arc<dyn Material>) -> (World, Camera) {
+    let mut world = World::new();
+    let backdrop: Arc<dyn Material> = Arc::new(Lambertian {
+        color: ColorType::Checker(ColumnVec([0.25; 3]), ColumnVec([0.5; 3]), 0.25),
+    });
+    world.push(Box::new(Parallelogram::new(
+        [
+            ColumnVec([-4., 0., 1.]),
+            ColumnVec([4., 0., 1.]),
+            ColumnVec([-4., 0., -3.]),
+        ],
+        backdrop.clone(),
+    )));
+    world.push(Box::new(Parallelogram::new(
+        [
+            ColumnVec([-4., 0., -3.]),
+            ColumnVec([4., 0., -3.]),
+            ColumnVec([-4., 4., -3.]),
+        ],
+        backdrop,
+    )));
+    world.push(Box::new(Parallelogram::new(
+        [
+            ColumnVec([-3., 2., 0.]),
+            ColumnVec([-2., 4., 0.]),
+            ColumnVec([-3., 2., -2.]),
+        ],
+        Arc::new(Emissive::blackbody(4500., 6.)),
+    )));
+    world.push(Box::new(Parallelogram::new(
+        [
+            ColumnVec([3., 1., 0.5]),
+            ColumnVec([3., 3., 0.5]),
+            ColumnVec([3., 1., -1.5]),
+        ],
+        Arc::new(Emissive::blackbody(8000., 1.5)),
+    )));
+    world.push(Box::new(Sphere {
+        center: ColumnVec([0., 0.8, -1.]),
+        radius: 0.8,
+        material,
+    }));
+    let camera = Camera::new(400, 400, 2.4, 1.5, ColumnVec([0., 1., 2.]));
+    (world, camera)
+}
+
+/// Renders `material` on the `material_ball` scene.
+pub fn render_material(material: Arc<dyn Material>, samples_per_pixel: u32, filename: &str) {
+    let (world, camera) = material_ball(material);
+    render(world, camera, samples_per_pixel, filename);
+}
+
+/// A unit sphere of `material` at the origin inside a uniform white environment. A white
+/// material that conserves energy looks exactly as bright as the environment from every side.
+pub fn furnace(material: Arc<dyn Material>) -> World {
+    let mut world = World::new();
+    world.backgrounds = Backgrounds::all(Background::Solid(ColumnVec([1.; 3])));
+    world.push(Box::new(Sphere {
+        center: ColumnVec::zero(),
+        radius: 1.,
+        material,
+    }));
+    world
+}
@@ -0,0 +1,203 @@
+use std::{
+    fs::File,
+    io::{self, Seek, SeekFrom, Write},
+};
+
+use image::Rgb;
+
+const SIGNATURE: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
+/// Largest payload of a stored (uncompressed) deflate block.
+const MAX_BLOCK: usize = 65535;
+const ADLER_MODULUS: i64 = 65521;
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+/// Appends a PNG chunk, with room for its CRC which `seal_chunk` fills in.
+fn push_chunk(bytes: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    bytes.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(kind);
+    bytes.extend_from_slice(data);
+    bytes.extend_from_slice(&[0; 4]);
+    let start = bytes.len() - data.len() - 12;
+    seal_chunk(bytes, start, data.len());
+}
+
+/// Recomputes the CRC of the chunk starting at `start` with `length` bytes of data.
+fn seal_chunk(bytes: &mut [u8], start: usize, length: usize) {
+    let crc = crc32(&bytes[start + 4..start + 8 + length]);
+    bytes[start + 8 + length..start + 12 + length].copy_from_slice(&crc.to_be_bytes());
+}
+
+/// A PNG file whose pixels can be updated in place without re-encoding the image, for
+/// keeping an up to date copy of a long render on disk. The image data is stored
+/// uncompressed with each row in its own IDAT chunk, so an update only rewrites the rows it
+/// touches and the checksum at the end. The file is kept in memory, and `write_column` only
+/// changes that copy until `flush` writes the changed rows out in one pass.
+pub struct Snapshot {
+    file: File,
+    bytes: Vec<u8>,
+    width: usize,
+    height: usize,
+    /// Offset of the first row's chunk.
+    rows_start: usize,
+    /// Length of each row's chunk, including its length, type and CRC.
+    row_chunk: usize,
+    /// Adler-32 sums of the image data, updated as pixels change.
+    adler: (i64, i64),
+    /// Rows changed since the last flush.
+    dirty: Option<(usize, usize)>,
+}
+
+impl Snapshot {
+    /// Creates the file as a black image.
+    pub fn create(filename: &str, width: u32, height: u32) -> io::Result<Self> {
+        let (width, height) = (width as usize, height as usize);
+        let row_length = 1 + 3 * width;
+        let blocks = row_length.div_ceil(MAX_BLOCK);
+        // filter byte and pixels, a header for each deflate block, and the chunk's own fields
+        let row_chunk = row_length + 5 * blocks + 12;
+
+        let mut bytes = SIGNATURE.to_vec();
+        let mut header = vec![];
+        header.extend_from_slice(&(width as u32).to_be_bytes());
+        header.extend_from_slice(&(height as u32).to_be_bytes());
+        // 8 bit RGB, default compression and filtering, not interlaced
+        header.extend_from_slice(&[8, 2, 0, 0, 0]);
+        push_chunk(&mut bytes, b"IHDR", &header);
+        // zlib header for a deflate stream with a 32 KB window
+        push_chunk(&mut bytes, b"IDAT", &[0x78, 0x01]);
+
+        let rows_start = bytes.len();
+        for y in 0..height {
+            let mut data = Vec::with_capacity(row_length + 5 * blocks);
+            for block in 0..blocks {
+                let length = (row_length - block * MAX_BLOCK).min(MAX_BLOCK) as u16;
+                let last = y == height - 1 && block == blocks - 1;
+                data.push(last as u8);
+                data.extend_from_slice(&length.to_le_bytes());
+                data.extend_from_slice(&(!length).to_le_bytes());
+                data.resize(data.len() + length as usize, 0);
+            }
+            push_chunk(&mut bytes, b"IDAT", &data);
+        }
+
+        // Every byte of the image data starts out zero.
+        let adler = (1, (height * row_length) as i64 % ADLER_MODULUS);
+        push_chunk(&mut bytes, b"IDAT", &[0; 4]);
+        push_chunk(&mut bytes, b"IEND", &[]);
+
+        let mut snapshot = Self {
+            file: File::create(filename)?,
+            bytes,
+            width,
+            height,
+            rows_start,
+            row_chunk,
+            adler,
+            dirty: None,
+        };
+        snapshot.seal_adler();
+        snapshot.file.write_all(&snapshot.bytes)?;
+        Ok(snapshot)
+    }
+
+    /// Offset of the checksum chunk, after the last row.
+    fn adler_chunk(&self) -> usize {
+        self.rows_start + self.height * self.row_chunk
+    }
+
+    fn seal_adler(&mut self) {
+        let start = self.adler_chunk();
+        let adler = ((self.adler.1 as u32) << 16) | self.adler.0 as u32;
+        self.bytes[start + 8..start + 12].copy_from_slice(&adler.to_be_bytes());
+        seal_chunk(&mut self.bytes, start, 4);
+    }
+
+    /// Sets the pixels of column `x` from the top down, in memory.
+    pub fn write_column(&mut self, x: u32, pixels: &[Rgb<u8>]) {
+        let row_length = 1 + 3 * self.width;
+        let total = (self.height * row_length) as i64;
+        for (y, pixel) in pixels.iter().enumerate().take(self.height) {
+            for (channel, &value) in pixel.0.iter().enumerate() {
+                // position in the row's data, after its filter byte
+                let position = 1 + 3 * x as usize + channel;
+                let block = position / MAX_BLOCK;
+                let offset = self.rows_start + y * self.row_chunk + 8 + 5 * (block + 1) + position;
+                let change = value as i64 - self.bytes[offset] as i64;
+                if change == 0 {
+                    continue;
+                }
+                self.bytes[offset] = value;
+                let index = (y * row_length + position) as i64;
+                self.adler.0 = (self.adler.0 + change).rem_euclid(ADLER_MODULUS);
+                self.adler.1 = (self.adler.1 + change * (total - index)).rem_euclid(ADLER_MODULUS);
+            }
+            self.dirty = Some(match self.dirty {
+                Some((first, last)) => (first.min(y), last.max(y)),
+                None => (y, y),
+            });
+        }
+    }
+
+    /// Writes the rows changed since the last flush to the file.
+    pub fn flush(&mut self) -> io::Result<()> {
+        let (first, last) = match self.dirty.take() {
+            Some(dirty) => dirty,
+            None => return Ok(()),
+        };
+        for y in first..=last {
+            let start = self.rows_start + y * self.row_chunk;
+            seal_chunk(&mut self.bytes, start, self.row_chunk - 12);
+        }
+        self.seal_adler();
+        let start = self.rows_start + first * self.row_chunk;
+        let end = self.rows_start + (last + 1) * self.row_chunk;
+        self.file.seek(SeekFrom::Start(start as u64))?;
+        self.file.write_all(&self.bytes[start..end])?;
+        let adler_chunk = self.adler_chunk();
+        self.file.seek(SeekFrom::Start(adler_chunk as u64))?;
+        self.file
+            .write_all(&self.bytes[adler_chunk..adler_chunk + 16])?;
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::RgbImage;
+
+    #[test]
+    fn snapshot_reads_back_as_the_written_image() {
+        let expected = RgbImage::from_fn(37, 11, |x, y| Rgb([x as u8 * 7, y as u8 * 23, 200]));
+        let filename = std::env::temp_dir().join(format!("snapshot-{}.png", std::process::id()));
+        let filename = filename.to_str().unwrap();
+        let mut snapshot = Snapshot::create(filename, 37, 11).unwrap();
+        // flush part way to check the file stays valid between updates
+        for x in (0..37).rev() {
+            let column: Vec<_> = (0..11).map(|y| *expected.get_pixel(x, y)).collect();
+            snapshot.write_column(x, &column);
+            if x == 20 {
+                snapshot.flush().unwrap();
+                assert!(image::open(filename).is_ok());
+            }
+        }
+        snapshot.flush().unwrap();
+        let written = image::open(filename).unwrap().to_rgb8();
+        std::fs::remove_file(filename).unwrap();
+        assert_eq!(written, expected);
+    }
+}
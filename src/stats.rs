@@ -0,0 +1,179 @@
+use image::{Rgb, RgbImage};
+
+use elgan_math::linalg::ColumnVec;
+
+use crate::render::{HdrBuffer, Progress};
+
+const GLYPH_SCALE: u32 = 2;
+
+/// 3x5 pixel glyphs, one row per entry with the leftmost pixel in the highest bit.
+fn glyph(c: char) -> [u8; 5] {
+    match c.to_ascii_lowercase() {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b110, 0b001, 0b010, 0b100, 0b111],
+        '3' => [0b110, 0b001, 0b010, 0b001, 0b110],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b110, 0b001, 0b110],
+        '6' => [0b011, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b010, 0b010, 0b010],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b110],
+        'a' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'b' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'c' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'd' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'e' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'f' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'g' => [0b011, 0b100, 0b101, 0b101, 0b011],
+        'h' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'i' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'j' => [0b001, 0b001, 0b001, 0b101, 0b010],
+        'k' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'l' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'm' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'n' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'o' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'p' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+        'r' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        's' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        't' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'u' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'v' => [0b101, 0b101, 0b101, 0b101, 0b010],
+        'w' => [0b101, 0b101, 0b111, 0b111, 0b101],
+        'x' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        'z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        ',' => [0b000, 0b000, 0b000, 0b010, 0b100],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '=' => [0b000, 0b111, 0b000, 0b111, 0b000],
+        '/' => [0b001, 0b001, 0b010, 0b100, 0b100],
+        '%' => [0b101, 0b001, 0b010, 0b100, 0b101],
+        '(' => [0b010, 0b100, 0b100, 0b100, 0b010],
+        ')' => [0b010, 0b001, 0b001, 0b001, 0b010],
+        _ => [0; 5],
+    }
+}
+
+/// Writes `text` with its top left corner at `x`, `y`, skipping pixels outside the image.
+pub fn draw_text(img: &mut RgbImage, x: u32, y: u32, text: &str, color: Rgb<u8>) {
+    for (index, c) in text.chars().enumerate() {
+        let left = x + index as u32 * 4 * GLYPH_SCALE;
+        for (row, bits) in glyph(c).into_iter().enumerate() {
+            for column in 0..3 {
+                if bits & (0b100 >> column) == 0 {
+                    continue;
+                }
+                for dy in 0..GLYPH_SCALE {
+                    for dx in 0..GLYPH_SCALE {
+                        let px = left + column * GLYPH_SCALE + dx;
+                        let py = y + row as u32 * GLYPH_SCALE + dy;
+                        if px < img.width() && py < img.height() {
+                            img.put_pixel(px, py, color);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// From blue at 0 to red at 1.
+fn heat_color(t: f64) -> [f64; 3] {
+    [t * 255., 40., (1. - t) * 255.]
+}
+
+/// The rendered image tinted by how long each column took, from blue for the fastest to
+/// red for the slowest, with `lines` of notes in the top left corner.
+pub fn stats_image(progress: &Progress, lines: &[String]) -> RgbImage {
+    let mut img = progress.image.lock().unwrap().clone();
+    let times: Vec<f64> = progress
+        .column_times
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|time| time.map_or(0., |time| time.as_secs_f64()))
+        .collect();
+    let slowest = times.iter().cloned().fold(f64::MIN_POSITIVE, f64::max);
+    for (x, y, pixel) in img.enumerate_pixels_mut() {
+        let heat = heat_color(times[x as usize] / slowest);
+        for (channel, heat) in pixel.0.iter_mut().zip(heat) {
+            *channel = (*channel as f64 * 0.4 + heat * 0.6) as u8;
+        }
+        if y < (lines.len() as u32 * 6 + 2) * GLYPH_SCALE {
+            pixel.0 = pixel.0.map(|c| c / 4);
+        }
+    }
+    for (index, line) in lines.iter().enumerate() {
+        draw_text(
+            &mut img,
+            2 * GLYPH_SCALE,
+            (index as u32 * 6 + 1) * GLYPH_SCALE,
+            line,
+            Rgb([255; 3]),
+        );
+    }
+    img
+}
+
+/// How long each pixel took to render, from blue for the fastest to red for the slowest,
+/// or none if pixel times weren't recorded with `Progress::with_pixel_times`.
+/// The scale is logarithmic, since a few very slow pixels would otherwise wash out the rest.
+pub fn pixel_time_image(progress: &Progress) -> Option<RgbImage> {
+    let times = progress.pixel_times.as_ref()?.lock().unwrap();
+    let (width, height) = progress.image.lock().unwrap().dimensions();
+    let log_times: Vec<f64> = times
+        .iter()
+        .map(|time| time.as_secs_f64().max(1e-9).ln())
+        .collect();
+    let fastest = log_times.iter().cloned().fold(f64::INFINITY, f64::min);
+    let slowest = log_times.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    let range = (slowest - fastest).max(f64::MIN_POSITIVE);
+    Some(RgbImage::from_fn(width, height, |x, y| {
+        let t = (log_times[(y * width + x) as usize] - fastest) / range;
+        Rgb(heat_color(t).map(|c| c as u8))
+    }))
+}
+
+/// Relative luminance of a linear Rec.709 color.
+pub fn luminance(color: ColumnVec<3>) -> f64 {
+    0.2126 * color[0] + 0.7152 * color[1] + 0.0722 * color[2]
+}
+
+/// Exposure of a pixel in stops relative to middle gray (18% luminance).
+fn stops(color: ColumnVec<3>) -> f64 {
+    (luminance(color) / 0.18).max(1e-12).log2()
+}
+
+/// How many pixels fall in each one stop wide bin of exposure relative to middle gray,
+/// from `min` stops up to `max`. Pixels outside the range are counted in the end bins.
+pub fn luminance_histogram(hdr: &HdrBuffer, min: i32, max: i32) -> Vec<u32> {
+    let bins = (max - min).max(1) as usize;
+    let mut histogram = vec![0; bins];
+    for index in 0..hdr.len() {
+        let bin = (stops(hdr.get(index)).floor() as i64 - min as i64).clamp(0, bins as i64 - 1);
+        histogram[bin as usize] += 1;
+    }
+    histogram
+}
+
+/// Colors each pixel by its exposure relative to middle gray, like the false color view of a
+/// camera monitor: purple and blue for crushed shadows, green around middle gray, yellow
+/// for bright highlights and red for more than four stops over.
+pub fn false_color(hdr: &HdrBuffer, width: u32, height: u32) -> RgbImage {
+    RgbImage::from_fn(width, height, |x, y| {
+        let stops = stops(hdr.get((y * width + x) as usize));
+        Rgb(match stops {
+            s if s < -4. => [80, 0, 120],
+            s if s < -2. => [0, 60, 200],
+            s if s < -0.5 => [90, 90, 90],
+            s if s < 0.5 => [0, 190, 0],
+            s if s < 2. => [170, 170, 170],
+            s if s < 4. => [230, 200, 0],
+            _ => [230, 0, 0],
+        })
+    })
+}